@@ -1,27 +1,141 @@
-use anyhow::Result;
+use crate::stream::{self, Progress};
+use anyhow::{bail, Result};
 use itertools::Itertools;
 use nom::{AsChar, ToUsize};
+use std::io::BufRead;
 
 static WORDY_DIGITS: [&str; 10] = [
     "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
 ];
 
+/// How the digit tokens found on a line are turned into a calibration value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combiner {
+    /// First digit followed by last digit, the puzzle's definition.
+    FirstLast,
+    /// Every digit on the line concatenated in order.
+    Concatenated,
+    Sum,
+    Max,
+}
+
+/// What to do with a line that contains no digit tokens at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MissingDigits {
+    Error,
+    Skip,
+}
+
+impl Combiner {
+    fn combine(&self, digits: &[usize]) -> Option<usize> {
+        match self {
+            Combiner::FirstLast => Some(digits.first()? * 10 + digits.last()?),
+            Combiner::Concatenated => {
+                if digits.is_empty() {
+                    return None;
+                }
+                digits
+                    .iter()
+                    .try_fold(0_usize, |acc, d| acc.checked_mul(10)?.checked_add(*d))
+            }
+            Combiner::Sum => {
+                if digits.is_empty() {
+                    None
+                } else {
+                    Some(digits.iter().sum())
+                }
+            }
+            Combiner::Max => digits.iter().max().cloned(),
+        }
+    }
+}
+
+/// Every digit token on the line, in order. When `words` is set, spelled out
+/// digits ("one", "two", ...) count as tokens too, and overlapping words such
+/// as "twone" yield both digits.
+fn digit_tokens(mut line: &str, words: bool) -> Vec<usize> {
+    if !words {
+        return line
+            .bytes()
+            .filter(|c| c.is_dec_digit())
+            .map(|c| (c - b'0').to_usize())
+            .collect_vec();
+    }
+
+    let mut digits = Vec::new();
+    while let Some((idx, d)) = next_digit(line) {
+        digits.push(d);
+        line = &line[idx + 1..];
+    }
+    digits
+}
+
+/// Calibration value of the `n`th line, or `None` when the line has no digit
+/// token and `missing` says to skip it.
+fn line_calibration(
+    n: usize,
+    line: &str,
+    words: bool,
+    combiner: Combiner,
+    missing: MissingDigits,
+) -> Result<Option<usize>> {
+    let digits = match combiner {
+        Combiner::FirstLast if words => first_last_digits(line)
+            .map(|(f, l)| vec![f, l])
+            .unwrap_or_default(),
+        _ => digit_tokens(line, words),
+    };
+    if digits.is_empty() {
+        return match missing {
+            MissingDigits::Skip => Ok(None),
+            MissingDigits::Error => bail!("line {} has no digits: {:?}", n + 1, line),
+        };
+    }
+    match combiner.combine(&digits) {
+        Some(v) => Ok(Some(v)),
+        None => bail!("line {} overflows with {:?}: {:?}", n + 1, combiner, line),
+    }
+}
+
+/// Calibration value of each line of `input`. Lines without any digit token
+/// are either dropped or reported as an error depending on `missing`.
+fn calibration_values(
+    input: &str,
+    words: bool,
+    combiner: Combiner,
+    missing: MissingDigits,
+) -> Result<Vec<usize>> {
+    let mut values = Vec::new();
+    for (n, line) in input.lines().enumerate() {
+        values.extend(line_calibration(n, line, words, combiner, missing)?);
+    }
+    Ok(values)
+}
+
+/// Sum of the calibration values read line by line from `reader`, for inputs
+/// too large to hold in memory.
+fn calibration_sum_stream(
+    reader: impl BufRead,
+    words: bool,
+    combiner: Combiner,
+    missing: MissingDigits,
+    report: impl FnMut(Progress),
+) -> Result<usize> {
+    let mut sum = 0;
+    stream::for_each_line(reader, report, |n, line| {
+        sum += line_calibration(n, line, words, combiner, missing)?.unwrap_or_default();
+        Ok(())
+    })?;
+    Ok(sum)
+}
+
 fn solve_part1() -> Result<usize> {
     let data = include_str!("input.txt");
-    Ok(data
-        .lines()
-        .map(|line| {
-            let digits = line
-                .bytes()
-                .filter(|c| c.is_dec_digit())
-                .map(|c| c - b'0')
-                .collect_vec();
-            let s = digits.first().unwrap().to_usize();
-            let e = digits.last().unwrap().to_usize();
-
-            s * 10 + e
-        })
-        .sum())
+    Ok(
+        calibration_values(data, false, Combiner::FirstLast, MissingDigits::Skip)?
+            .into_iter()
+            .sum(),
+    )
 }
 
 fn next_digit(s: &str) -> Option<(usize, usize)> {
@@ -60,41 +174,54 @@ fn next_digit(s: &str) -> Option<(usize, usize)> {
     }
 }
 
-fn solve_part2() -> Result<usize> {
-    let data = include_str!("input.txt");
-    Ok(data
-        .lines()
-        .map(|mut line| {
-            let mut digits = Vec::new();
-
-            loop {
-                match next_digit(line) {
-                    None => break,
-                    Some((idx, d)) => {
-                        digits.push(d);
-                        let start = idx + 1;
-                        if start > line.len() {
-                            break;
-                        }
-                        line = &line[start..];
-                        if line.is_empty() {
-                            break;
-                        }
-                    }
-                }
-            }
-
-            let s = digits.first().unwrap().to_usize();
-            let e = digits.last().unwrap().to_usize();
+/// Which end of the line a digit scan starts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
 
-            let d = s * 10 + e;
-            d
+/// Finds the first digit (spelled out or not) met when walking the line in
+/// `direction`. Walking backwards matches each word against the bytes ending
+/// at the cursor, i.e. the reversed word against the reversed line, so the
+/// last digit is found without scanning the rest of the line.
+fn scan_digit(s: &str, direction: Direction) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let matches_at = |i: usize| -> Option<usize> {
+        if bytes[i].is_ascii_digit() {
+            return Some((bytes[i] - b'0').to_usize());
+        }
+        WORDY_DIGITS.iter().position(|w| match direction {
+            Direction::Forward => bytes[i..].starts_with(w.as_bytes()),
+            Direction::Backward => bytes[..=i].ends_with(w.as_bytes()),
         })
-        .sum())
+    };
+    match direction {
+        Direction::Forward => (0..bytes.len()).find_map(matches_at),
+        Direction::Backward => (0..bytes.len()).rev().find_map(matches_at),
+    }
+}
+
+/// First and last digit of a line, found with one scan from each end.
+fn first_last_digits(line: &str) -> Option<(usize, usize)> {
+    let first = scan_digit(line, Direction::Forward)?;
+    let last = scan_digit(line, Direction::Backward)?;
+    Some((first, last))
 }
+
+fn solve_part2() -> Result<usize> {
+    let data = include_str!("input.txt");
+    Ok(
+        calibration_values(data, true, Combiner::FirstLast, MissingDigits::Skip)?
+            .into_iter()
+            .sum(),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::rng::Rng;
 
     #[test]
     fn day1_part1() -> Result<()> {
@@ -111,4 +238,87 @@ mod test {
         assert_eq!(s, 54925);
         Ok(())
     }
+
+    #[test]
+    fn day1_calibration_combiners() -> Result<()> {
+        let input = "two1nine\neightwothree\n\nabcone2threexyz";
+        let first_last = calibration_values(input, true, Combiner::FirstLast, MissingDigits::Skip)?;
+        assert_eq!(first_last, vec![29, 83, 13]);
+        assert_eq!(digit_tokens("eightwothree", true), vec![8, 2, 3]);
+        assert_eq!(
+            calibration_values(input, true, Combiner::Concatenated, MissingDigits::Skip)?,
+            vec![219, 823, 123]
+        );
+        assert_eq!(
+            calibration_values(input, true, Combiner::Sum, MissingDigits::Skip)?,
+            vec![12, 13, 6]
+        );
+        assert_eq!(
+            calibration_values(input, false, Combiner::Max, MissingDigits::Skip)?,
+            vec![1, 2]
+        );
+        assert!(
+            calibration_values(input, true, Combiner::FirstLast, MissingDigits::Error).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn day1_stream() -> Result<()> {
+        let input = "two1nine\neightwothree\nabcone2threexyz\n";
+        let mut reports = Vec::new();
+        let sum = calibration_sum_stream(
+            input.as_bytes(),
+            true,
+            Combiner::FirstLast,
+            MissingDigits::Error,
+            |p| reports.push(p.lines),
+        )?;
+        assert_eq!(sum, 29 + 83 + 13);
+        assert_eq!(reports, vec![3]);
+        Ok(())
+    }
+
+    #[test]
+    fn day1_reverse_scan() {
+        assert_eq!(scan_digit("eightwothree", Direction::Backward), Some(3));
+        assert_eq!(scan_digit("xtwone", Direction::Backward), Some(1));
+        assert_eq!(scan_digit("xtwone", Direction::Forward), Some(2));
+        assert_eq!(first_last_digits("zoneight234"), Some((1, 4)));
+        assert_eq!(first_last_digits("abc"), None);
+    }
+
+    /// Compares walking the whole line with `next_digit` against two short
+    /// scans. Run with `cargo test --release day1_scan_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn day1_scan_benchmark() {
+        let alphabet = b"abcdefghijklmnopqrstuvwxyz123456789";
+        let mut rng = Rng::new(27);
+        let lines = (0..20_000)
+            .map(|_| {
+                (0..400)
+                    .map(|_| alphabet[rng.below(alphabet.len())] as char)
+                    .collect::<String>()
+            })
+            .collect_vec();
+
+        let start = std::time::Instant::now();
+        let walked: usize = lines
+            .iter()
+            .flat_map(|l| Combiner::FirstLast.combine(&digit_tokens(l, true)))
+            .sum();
+        let walk_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let scanned: usize = lines
+            .iter()
+            .flat_map(|l| first_last_digits(l))
+            .map(|(f, l)| f * 10 + l)
+            .sum();
+        let scan_time = start.elapsed();
+
+        println!("walk: {:?}, two scans: {:?}", walk_time, scan_time);
+        assert_eq!(walked, scanned);
+    }
 }
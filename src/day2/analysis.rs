@@ -0,0 +1,144 @@
+use super::{Bag, Color, Game};
+use crate::rng::Rng;
+use itertools::Itertools;
+use num::{integer::binomial, BigRational, BigUint, ToPrimitive, Zero};
+use std::collections::HashMap;
+
+/// Probability of revealing exactly `set` when grabbing that many cubes from
+/// `bag` at once (multivariate hypergeometric).
+fn set_likelihood(set: &HashMap<Color, usize>, bag: &Bag) -> BigRational {
+    let total: usize = bag.cubes.values().sum();
+    let drawn: usize = set.values().sum();
+    if drawn > total {
+        return BigRational::zero();
+    }
+    let ways = set
+        .iter()
+        .map(|(c, k)| binomial(BigUint::from(bag.count(c)), BigUint::from(*k)))
+        .fold(BigUint::from(1_u32), |acc, w| acc * w);
+    let all = binomial(BigUint::from(total), BigUint::from(drawn));
+    BigRational::new(ways.into(), all.into())
+}
+
+/// Exact probability that `bag` produced every revealed set of `game`, the
+/// cubes being put back in the bag between sets.
+pub(super) fn likelihood(game: &Game, bag: &Bag) -> BigRational {
+    game.sets
+        .iter()
+        .map(|set| set_likelihood(set, bag))
+        .fold(BigRational::from_integer(1.into()), |acc, l| acc * l)
+}
+
+/// Every bag holding exactly `total` cubes spread over `colors`. Colours with
+/// no cubes are left out of the bag.
+pub(super) fn bags_with_total(colors: &[Color], total: usize) -> Vec<Bag> {
+    match colors.split_first() {
+        None => vec![Bag::default()],
+        Some((c, [])) => vec![[(c.clone(), total)]
+            .into_iter()
+            .filter(|(_, n)| *n > 0)
+            .collect()],
+        Some((c, rest)) => (0..=total)
+            .flat_map(|n| {
+                bags_with_total(rest, total - n)
+                    .into_iter()
+                    .map(move |mut b| {
+                        if n > 0 {
+                            b.cubes.insert(c.clone(), n);
+                        }
+                        b
+                    })
+            })
+            .collect_vec(),
+    }
+}
+
+/// Posterior probability of each candidate bag given the game, with a uniform
+/// prior over `candidates`. Candidates that can't explain the game are dropped.
+pub(super) fn posterior(game: &Game, candidates: &[Bag]) -> Vec<(Bag, f64)> {
+    let weighted = candidates
+        .iter()
+        .map(|b| (b, likelihood(game, b)))
+        .filter(|(_, l)| !l.is_zero())
+        .collect_vec();
+    let evidence = weighted
+        .iter()
+        .fold(BigRational::zero(), |acc, (_, l)| acc + l);
+    weighted
+        .into_iter()
+        .map(|(b, l)| {
+            let p = (l / &evidence).to_f64().unwrap_or_default();
+            (b.clone(), p)
+        })
+        .collect_vec()
+}
+
+/// The candidate bag under which the game is most likely.
+pub(super) fn most_likely_bag(game: &Game, candidates: &[Bag]) -> Option<(Bag, BigRational)> {
+    candidates
+        .iter()
+        .map(|b| (b.clone(), likelihood(game, b)))
+        .filter(|(_, l)| !l.is_zero())
+        .max_by(|a, b| a.1.cmp(&b.1))
+}
+
+/// Grabs `size` cubes at random from `bag` without putting them back.
+pub(super) fn draw(bag: &Bag, size: usize, rng: &mut Rng) -> HashMap<Color, usize> {
+    let mut cubes = bag
+        .cubes
+        .iter()
+        .sorted()
+        .flat_map(|(c, n)| std::iter::repeat_n(c, *n))
+        .collect_vec();
+    let mut set = HashMap::new();
+    for i in 0..size.min(cubes.len()) {
+        let j = i + rng.below(cubes.len() - i);
+        cubes.swap(i, j);
+        *set.entry(cubes[i].clone()).or_default() += 1;
+    }
+    set
+}
+
+/// Monte Carlo estimate of [`likelihood`]: each set is redrawn `trials` times
+/// and the fraction of exact matches is multiplied across sets.
+pub(super) fn estimate_likelihood(game: &Game, bag: &Bag, trials: usize, rng: &mut Rng) -> f64 {
+    game.sets
+        .iter()
+        .map(|set| {
+            let size = set.values().sum();
+            let hits = (0..trials).filter(|_| draw(bag, size, rng) == *set).count();
+            hits as f64 / trials as f64
+        })
+        .product()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn day2_likelihood() {
+        let (_, game) = Game::new("Game 1: 1 red, 1 blue; 2 red").unwrap();
+        let bag: Bag = [("red", 2), ("blue", 1)].into_iter().collect();
+        // 2/3 for the first set, 1/3 for the second.
+        assert_eq!(
+            likelihood(&game, &bag),
+            BigRational::new(2.into(), 9.into())
+        );
+
+        let mut rng = Rng::new(7);
+        let estimate = estimate_likelihood(&game, &bag, 20_000, &mut rng);
+        assert!((estimate - 2.0 / 9.0).abs() < 0.02, "{}", estimate);
+
+        let colors = [Color::from("red"), Color::from("blue")];
+        let candidates = bags_with_total(&colors, 4);
+        assert_eq!(candidates.len(), 5);
+        assert!(candidates.contains(&[("red", 4)].into_iter().collect()));
+        assert!(candidates.contains(&[("blue", 4)].into_iter().collect()));
+        assert_eq!(bags_with_total(&colors, 0), vec![Bag::default()]);
+        let post = posterior(&game, &candidates);
+        assert!((post.iter().map(|(_, p)| p).sum::<f64>() - 1.0).abs() < 1e-9);
+        let (best, _) = most_likely_bag(&game, &candidates).unwrap();
+        assert_eq!(best, [("red", 3), ("blue", 1)].into_iter().collect());
+    }
+}
@@ -1,30 +1,158 @@
-use anyhow::Result;
+mod analysis;
+
+use crate::rng::Rng;
+use crate::stream::{self, Progress};
+use anyhow::{bail, Result};
 use itertools::Itertools;
 use nom::{
     bytes::complete::tag, character::complete, combinator, multi::separated_list1,
     sequence::separated_pair, IResult,
 };
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::BufRead;
+
+/// A cube colour, any word the input uses.
+#[derive(Debug, Eq, PartialEq, Hash, Clone, PartialOrd, Ord)]
+struct Color(String);
+
+impl From<&str> for Color {
+    fn from(value: &str) -> Self {
+        Color(value.to_string())
+    }
+}
 
-#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
-enum Color {
-    Red,
-    Green,
-    Blue,
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Game {
     sets: Vec<HashMap<Color, usize>>,
     id: usize,
 }
 
+/// The cubes loaded in the bag before a game, by colour.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Bag {
+    cubes: HashMap<Color, usize>,
+}
+
 fn parse_color(line: &str) -> IResult<&str, Color> {
-    nom::branch::alt((
-        combinator::map(tag("red"), |_| Color::Red),
-        combinator::map(tag("green"), |_| Color::Green),
-        combinator::map(tag("blue"), |_| Color::Blue),
-    ))(line)
+    combinator::map(complete::alpha1, Color::from)(line)
+}
+
+/// A comma separated list of "<count> <colour>" entries, with repeated
+/// colours added together.
+fn parse_cubes(input: &str) -> IResult<&str, HashMap<Color, usize>> {
+    let (input, cubes) = separated_list1(
+        tag(", "),
+        separated_pair(
+            combinator::map(complete::u32, |v| v as usize),
+            complete::space1,
+            parse_color,
+        ),
+    )(input)?;
+
+    let mut res = HashMap::new();
+    for (n, c) in cubes {
+        res.entry(c).and_modify(|i| *i += n).or_insert(n);
+    }
+    Ok((input, res))
+}
+
+impl Bag {
+    /// Parses a bag written like a revealed set, e.g. "12 red, 13 green, 14 blue".
+    fn new(input: &str) -> IResult<&str, Self> {
+        let (input, cubes) = parse_cubes(input)?;
+        Ok((input, Bag { cubes }))
+    }
+
+    fn count(&self, color: &Color) -> usize {
+        self.cubes.get(color).cloned().unwrap_or_default()
+    }
+
+    /// Product of the cube counts of every colour in the bag.
+    fn power(&self) -> usize {
+        self.cubes.values().product()
+    }
+
+    /// Grows the bag so it holds at least as many cubes of each colour as `other`.
+    fn include(&mut self, other: &HashMap<Color, usize>) {
+        for (c, n) in other {
+            self.cubes
+                .entry(c.clone())
+                .and_modify(|f| *f = (*f).max(*n))
+                .or_insert(*n);
+        }
+    }
+}
+
+/// Per-colour cube counts across a number of revealed sets. Sets where the
+/// colour wasn't drawn count as zero towards the mean.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ColorStats {
+    max: usize,
+    total: usize,
+    mean: f64,
+}
+
+fn color_stats<'a>(
+    sets: impl IntoIterator<Item = &'a HashMap<Color, usize>>,
+) -> HashMap<Color, ColorStats> {
+    let mut stats: HashMap<Color, ColorStats> = HashMap::new();
+    let mut set_count = 0;
+    for set in sets {
+        set_count += 1;
+        for (c, n) in set {
+            let s = stats.entry(c.clone()).or_default();
+            s.max = s.max.max(*n);
+            s.total += n;
+        }
+    }
+    for s in stats.values_mut() {
+        s.mean = s.total as f64 / set_count as f64;
+    }
+    stats
+}
+
+/// Statistics over a whole list of games.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct GamesStats {
+    games: usize,
+    sets: usize,
+    /// The smallest bag with which every game is possible.
+    minimum_bag: Bag,
+    colors: HashMap<Color, ColorStats>,
+}
+
+impl GamesStats {
+    fn new<'a>(games: impl IntoIterator<Item = &'a Game>) -> Self {
+        let mut stats = GamesStats::default();
+        let mut sets = Vec::new();
+        for g in games {
+            stats.games += 1;
+            sets.extend(g.sets.iter());
+        }
+        for set in sets.iter() {
+            stats.minimum_bag.include(set);
+        }
+        stats.sets = sets.len();
+        stats.colors = color_stats(sets);
+        stats
+    }
+}
+
+impl<C: Into<Color>> FromIterator<(C, usize)> for Bag {
+    fn from_iter<T: IntoIterator<Item = (C, usize)>>(iter: T) -> Self {
+        let mut cubes = HashMap::new();
+        for (c, n) in iter {
+            *cubes.entry(c.into()).or_default() += n;
+        }
+        Bag { cubes }
+    }
 }
 
 impl Game {
@@ -32,28 +160,7 @@ impl Game {
         let (input, _) = tag("Game ")(input)?;
         let (input, id) = complete::u32(input)?;
         let (input, _) = tag(": ")(input)?;
-        let (input, sets) = separated_list1(
-            tag("; "),
-            separated_list1(
-                tag(", "),
-                separated_pair(
-                    combinator::map(complete::u32, |v| v as usize),
-                    complete::space1,
-                    parse_color,
-                ),
-            ),
-        )(input)?;
-
-        let sets = sets
-            .into_iter()
-            .map(|set| {
-                let mut res = HashMap::new();
-                for (n, c) in set {
-                    res.entry(c).and_modify(|i| *i += n).or_insert(n);
-                }
-                res
-            })
-            .collect_vec();
+        let (input, sets) = separated_list1(tag("; "), parse_cubes)(input)?;
 
         Ok((
             input,
@@ -64,49 +171,154 @@ impl Game {
         ))
     }
 
+    /// The smallest bag with which this game is possible.
+    fn minimum_bag(&self) -> Bag {
+        let mut bag = Bag::default();
+        for set in self.sets.iter() {
+            bag.include(set);
+        }
+        bag
+    }
+
+    fn color_stats(&self) -> HashMap<Color, ColorStats> {
+        color_stats(self.sets.iter())
+    }
+
     fn power(&self) -> usize {
-        let mut s: HashMap<Color, usize> = HashMap::new();
+        self.minimum_bag().power()
+    }
 
-        for set in self.sets.iter() {
-            for (c, n) in set {
-                s.entry(*c).and_modify(|f| *f = (*f).max(*n)).or_insert(*n);
+    /// Whether every revealed set could have been drawn from `bag`. Colours
+    /// missing from the bag count as zero cubes.
+    fn is_possible_with(&self, bag: &Bag) -> bool {
+        self.sets
+            .iter()
+            .all(|set| set.iter().all(|(c, n)| *n <= bag.count(c)))
+    }
+}
+
+/// Writes the game back in the input format. Colours within a set are listed
+/// in name order, which is the canonical form `Game::new` reads back to an
+/// equal game.
+impl Display for Game {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Game {}: ", self.id)?;
+        for (i, set) in self.sets.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            for (j, (c, n)) in set.iter().sorted().enumerate() {
+                if j > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{} {}", n, c)?;
             }
         }
-        s.values().product()
+        Ok(())
     }
 }
 
-pub fn solve_task1(file_content: &str) -> usize {
-    let available: HashMap<Color, usize> =
-        vec![(Color::Red, 12), (Color::Green, 13), (Color::Blue, 14)]
-            .into_iter()
-            .collect();
-    file_content
-        .lines()
-        .flat_map(|line| match Game::new(line) {
-            Err(_) => None,
-            Ok((_, g)) => Some(g),
-        })
-        .filter(|game| {
-            available.iter().all(|(c, n)| {
-                game.sets
-                    .iter()
-                    .all(|p| p.get(c).cloned().unwrap_or_default() <= *n)
+impl Game {
+    /// A random game with up to `max_sets` non empty sets, each drawing between
+    /// 1 and `max_count` cubes of some of `colors`. Fails if any of those is
+    /// empty or zero, as no such game exists.
+    fn random(
+        id: usize,
+        colors: &[Color],
+        max_sets: usize,
+        max_count: usize,
+        rng: &mut Rng,
+    ) -> Result<Self> {
+        if colors.is_empty() || max_sets == 0 || max_count == 0 {
+            bail!(
+                "can't draw games from {} colors, {} sets and {} cubes",
+                colors.len(),
+                max_sets,
+                max_count
+            );
+        }
+        let sets = (0..1 + rng.below(max_sets))
+            .map(|_| {
+                let mut set = HashMap::new();
+                while set.is_empty() {
+                    for c in colors {
+                        if rng.below(2) == 1 {
+                            set.insert(c.clone(), 1 + rng.below(max_count));
+                        }
+                    }
+                }
+                set
             })
-        })
-        .map(|g| g.id)
-        .sum()
+            .collect_vec();
+        Ok(Game { sets, id })
+    }
+}
+
+/// A synthetic puzzle input of `games` random games, one per line.
+fn random_input(games: usize, colors: &[Color], rng: &mut Rng) -> Result<String> {
+    let games = (1..=games)
+        .map(|id| Game::random(id, colors, 6, 20, rng))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(games.iter().join("\n"))
+}
+
+fn task1_bag() -> Bag {
+    [("red", 12), ("green", 13), ("blue", 14)]
+        .into_iter()
+        .collect()
+}
+
+fn parse_games(file_content: &str) -> impl Iterator<Item = Game> + '_ {
+    file_content.lines().flat_map(|line| match Game::new(line) {
+        Err(_) => None,
+        Ok((_, g)) => Some(g),
+    })
+}
+
+/// The games of `file_content` that could have been played with `bag`.
+fn possible_games<'a>(file_content: &'a str, bag: &'a Bag) -> impl Iterator<Item = Game> + 'a {
+    parse_games(file_content).filter(|g| g.is_possible_with(bag))
+}
+
+pub fn solve_task1(file_content: &str) -> usize {
+    let bag = task1_bag();
+    possible_games(file_content, &bag).map(|g| g.id).sum()
 }
 
 pub fn solve_task2(file_content: &str) -> usize {
-    file_content
-        .lines()
-        .flat_map(|line| match Game::new(line) {
-            Err(_) => None,
-            Ok((_, g)) => Some(g),
-        })
-        .map(|g| g.power())
-        .sum()
+    parse_games(file_content).map(|g| g.power()).sum()
+}
+
+/// Parses games line by line from `reader`, skipping lines that aren't games
+/// just like the `&str` solvers do.
+fn for_each_game(
+    reader: impl BufRead,
+    report: impl FnMut(Progress),
+    mut f: impl FnMut(Game),
+) -> Result<Progress> {
+    stream::for_each_line(reader, report, |_, line| {
+        if let Ok((_, g)) = Game::new(line) {
+            f(g);
+        }
+        Ok(())
+    })
+}
+
+pub fn solve_task1_stream(reader: impl BufRead, report: impl FnMut(Progress)) -> Result<usize> {
+    let bag = task1_bag();
+    let mut sum = 0;
+    for_each_game(reader, report, |g| {
+        if g.is_possible_with(&bag) {
+            sum += g.id;
+        }
+    })?;
+    Ok(sum)
+}
+
+pub fn solve_task2_stream(reader: impl BufRead, report: impl FnMut(Progress)) -> Result<usize> {
+    let mut sum = 0;
+    for_each_game(reader, report, |g| sum += g.power())?;
+    Ok(sum)
 }
 
 #[cfg(test)]
@@ -128,4 +340,95 @@ mod test {
         assert_eq!(s, 78669);
         Ok(())
     }
+
+    #[test]
+    fn day2_stream() -> Result<()> {
+        let input = "Game 1: 3 blue, 4 red; 1 red, 2 green, 6 blue; 2 green
+Game 2: 1 blue, 2 green; 3 green, 4 blue, 1 red; 1 green, 1 blue
+Game 3: 8 green, 6 blue, 20 red; 5 blue, 4 red, 13 green; 5 green, 1 red
+Game 4: 1 green, 3 red, 6 blue; 3 green, 6 red; 3 green, 15 blue, 14 red
+Game 5: 6 red, 1 blue, 3 green; 2 blue, 1 red, 2 green
+";
+        assert_eq!(solve_task1_stream(input.as_bytes(), |_| {})?, 8);
+        assert_eq!(solve_task2_stream(input.as_bytes(), |_| {})?, 2286);
+        assert_eq!(solve_task1(input), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn day2_custom_bag() {
+        let input = "Game 1: 3 blue, 4 red; 2 purple
+Game 2: 1 blue, 2 green; 3 green, 4 blue, 1 red
+Game 3: 5 purple, 1 red";
+        let (_, bag) = Bag::new("4 red, 3 green, 4 blue, 2 purple").unwrap();
+        let ids = possible_games(input, &bag).map(|g| g.id).collect_vec();
+        assert_eq!(ids, vec![1, 2]);
+
+        let no_purple: Bag = [("red", 4), ("green", 3), ("blue", 4)]
+            .into_iter()
+            .collect();
+        assert_eq!(bag.count(&Color::from("purple")), 2);
+        let ids = possible_games(input, &no_purple)
+            .map(|g| g.id)
+            .collect_vec();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn day2_stats() {
+        let input = "Game 1: 3 blue, 4 red; 1 red, 2 green, 6 blue; 2 green
+Game 2: 1 blue, 2 green; 3 green, 4 blue, 1 red; 1 green, 1 blue";
+        let games = parse_games(input).collect_vec();
+        let red = Color::from("red");
+        let blue = Color::from("blue");
+
+        let (_, expected) = Bag::new("4 red, 2 green, 6 blue").unwrap();
+        assert_eq!(games[0].minimum_bag(), expected);
+        assert_eq!(games[0].power(), 48);
+
+        let stats = games[0].color_stats();
+        assert_eq!(
+            stats[&red],
+            ColorStats {
+                max: 4,
+                total: 5,
+                mean: 5.0 / 3.0
+            }
+        );
+
+        let all = GamesStats::new(games.iter());
+        assert_eq!((all.games, all.sets), (2, 6));
+        let (_, expected) = Bag::new("4 red, 3 green, 6 blue").unwrap();
+        assert_eq!(all.minimum_bag, expected);
+        assert_eq!(all.colors[&blue].total, 15);
+        assert!(games.iter().all(|g| g.is_possible_with(&all.minimum_bag)));
+    }
+
+    #[test]
+    fn day2_round_trip() -> Result<()> {
+        let (_, g) = Game::new("Game 1: 3 blue, 4 red; 1 red, 2 green, 6 blue; 2 green").unwrap();
+        assert_eq!(
+            g.to_string(),
+            "Game 1: 3 blue, 4 red; 6 blue, 2 green, 1 red; 2 green"
+        );
+
+        let colors = ["red", "green", "blue", "purple"].map(Color::from);
+        let mut rng = Rng::new(2023);
+        for id in 1..500 {
+            let g = Game::random(id, &colors, 6, 20, &mut rng)?;
+            let text = g.to_string();
+            let (rest, parsed) = Game::new(&text).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(parsed, g, "{}", text);
+        }
+
+        let input = random_input(100, &colors, &mut rng)?;
+        assert_eq!(parse_games(&input).count(), 100);
+
+        assert!(Game::random(1, &[], 6, 20, &mut rng).is_err());
+        assert!(Game::random(1, &colors, 0, 20, &mut rng).is_err());
+        assert!(Game::random(1, &colors, 6, 0, &mut rng).is_err());
+        assert!(random_input(3, &[], &mut rng).is_err());
+        Ok(())
+    }
 }
@@ -1,66 +1,336 @@
+use crate::stream::{self, Progress};
+use anyhow::{anyhow, bail, Result};
 use itertools::Itertools;
+use std::collections::VecDeque;
+use std::io::BufRead;
 
-fn parse_number(input: &str) -> Vec<u32> {
+fn parse_number(input: &str) -> Result<Vec<u32>> {
     input
         .split_ascii_whitespace()
-        .flat_map(|n| {
-            if let Ok(i) = n.parse::<u32>() {
-                Some(i)
-            } else {
-                None
-            }
+        .map(|n| {
+            n.parse::<u32>()
+                .map_err(|e| anyhow!("bad number {:?}: {}", n, e))
         })
-        .collect_vec()
+        .collect()
 }
-fn str_to_card_matches(line: &str) -> Option<usize> {
-    let (_card_title, numbers) = line.split_once(':')?;
+/// Numbers from this one up don't go into a [`NumberSet`], which would take
+/// one bit for every smaller value.
+const NUMBER_SET_LIMIT: u32 = 1 << 12;
 
-    let (win_numbers_str, given_numbers_str) = numbers.split_once('|')?;
+/// Set of small numbers stored one bit per value, so intersecting two cards is
+/// a handful of `AND`s rather than a scan per number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct NumberSet(Vec<u64>);
 
-    let winning = parse_number(win_numbers_str);
+impl NumberSet {
+    /// `None` if a number reaches [`NUMBER_SET_LIMIT`].
+    fn new(numbers: &[u32]) -> Option<Self> {
+        if numbers.iter().any(|n| *n >= NUMBER_SET_LIMIT) {
+            return None;
+        }
+        let mut words = vec![0_u64; numbers.iter().max().map_or(0, |m| *m as usize / 64 + 1)];
+        for n in numbers {
+            words[*n as usize / 64] |= 1 << (n % 64);
+        }
+        Some(NumberSet(words))
+    }
 
-    Some(
-        parse_number(given_numbers_str)
-            .into_iter()
-            .filter(|c| winning.contains(c))
-            .count(),
-    )
+    fn intersection_len(&self, other: &NumberSet) -> usize {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Card {
+    id: usize,
+    winning: Vec<u32>,
+    given: Vec<u32>,
+}
+
+impl Card {
+    fn new(line: &str) -> Result<Self> {
+        let (card_title, numbers) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("missing ':' in card {:?}", line))?;
+        let id = card_title
+            .strip_prefix("Card")
+            .ok_or_else(|| anyhow!("missing 'Card' in {:?}", card_title))?
+            .trim()
+            .parse()?;
+        let (win_numbers_str, given_numbers_str) = numbers
+            .split_once('|')
+            .ok_or_else(|| anyhow!("missing '|' in card {}", id))?;
+
+        Ok(Self {
+            id,
+            winning: parse_number(win_numbers_str)?,
+            given: parse_number(given_numbers_str)?,
+        })
+    }
+
+    /// Checks that no number is repeated within the winning or given numbers.
+    fn validate(&self) -> Result<()> {
+        for (name, numbers) in [("winning", &self.winning), ("given", &self.given)] {
+            if let Some(n) = numbers.iter().duplicates().next() {
+                bail!("card {} repeats {} number {}", self.id, name, n);
+            }
+        }
+        Ok(())
+    }
+
+    /// How many given numbers are winning numbers. Repeated numbers only count
+    /// once, which `validate` guarantees makes no difference. Cards with
+    /// numbers too large for a bitset are matched through a sorted list.
+    fn matches(&self) -> usize {
+        match (NumberSet::new(&self.winning), NumberSet::new(&self.given)) {
+            (Some(winning), Some(given)) => winning.intersection_len(&given),
+            _ => {
+                let winning = self.winning.iter().sorted().dedup().collect_vec();
+                self.given
+                    .iter()
+                    .unique()
+                    .filter(|n| winning.binary_search(n).is_ok())
+                    .count()
+            }
+        }
+    }
+}
+
+/// Reads cards one line at a time, validating each and checking the IDs
+/// follow each other without gaps.
+#[derive(Debug, Clone, Default)]
+struct CardReader {
+    last_id: Option<usize>,
+}
+
+impl CardReader {
+    /// The card on `line`, or `None` for a blank line.
+    fn read(&mut self, line: &str) -> Result<Option<Card>> {
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let card = Card::new(line)?;
+        if let Some(prev) = self.last_id.filter(|prev| card.id != prev + 1) {
+            bail!("card {} follows card {}", card.id, prev);
+        }
+        card.validate()?;
+        self.last_id = Some(card.id);
+        Ok(Some(card))
+    }
 }
 
-fn solve_task1(file_content: &str) -> usize {
+/// Parses and validates every card.
+fn parse_cards(file_content: &str) -> Result<Vec<Card>> {
+    let mut reader = CardReader::default();
     file_content
         .lines()
-        .flat_map(str_to_card_matches)
-        .filter(|matches| *matches > 0)
-        .map(|matches| 1 << (matches - 1))
-        .sum()
+        .map(|l| reader.read(l))
+        .flatten_ok()
+        .collect()
 }
 
-fn solve_task2(file_content: &str) -> usize {
-    let matches = file_content
-        .lines()
-        .flat_map(str_to_card_matches)
-        .collect::<Vec<_>>();
-    let mut cards_instances = vec![1; matches.len()];
-    let mut sum = 0;
-    for (i, matches) in matches.into_iter().enumerate() {
-        let j0 = i + 1;
-        let j1 = usize::min(cards_instances.len(), i + matches + 1);
-        for j in j0..j1 {
-            cards_instances[j] += cards_instances[i];
+/// How many points a card is worth for its number of matches.
+enum ScoringRule {
+    /// 1 point for the first match, doubled for each one after (the puzzle).
+    Doubling,
+    /// 1 point per match.
+    Linear,
+    /// 1, 2, 3, 5, 8, ... points for 1, 2, 3, 4, 5, ... matches.
+    Fibonacci,
+    Custom(Box<dyn Fn(usize) -> usize>),
+}
+
+impl ScoringRule {
+    /// `None` if the score doesn't fit in a `usize`.
+    fn score(&self, matches: usize) -> Option<usize> {
+        if matches == 0 {
+            return Some(0);
+        }
+        match self {
+            ScoringRule::Doubling => 1usize.checked_shl(u32::try_from(matches - 1).ok()?),
+            ScoringRule::Linear => Some(matches),
+            ScoringRule::Fibonacci => (1..matches)
+                .try_fold((1usize, 1usize), |(a, b), _| Some((b, a.checked_add(b)?)))
+                .map(|(_, b)| b),
+            ScoringRule::Custom(f) => Some(f(matches)),
+        }
+    }
+
+    /// Adds the score of `card` to `sum`, failing on overflow.
+    fn add_score(&self, sum: usize, card: &Card) -> Result<usize> {
+        self.score(card.matches())
+            .and_then(|points| sum.checked_add(points))
+            .ok_or_else(|| anyhow!("score overflows at card {}", card.id))
+    }
+}
+
+/// Which cards a winning card hands out copies of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyRule {
+    /// The next `matches` cards, none past the end of the table (the puzzle).
+    NextN,
+    /// The `matches` cards before this one, none before the first.
+    PreviousN,
+    /// The next `matches` cards, going back to the first card after the last.
+    /// A card never wins a copy of itself.
+    Wraparound,
+    /// Like `NextN` but never more than this many cards.
+    Capped(usize),
+}
+
+impl CopyRule {
+    /// Indices of the cards won by the card at index `card` out of `len`.
+    fn targets(&self, card: usize, matches: usize, len: usize) -> Vec<usize> {
+        match self {
+            CopyRule::NextN => (card + 1..usize::min(len, card + matches + 1)).collect_vec(),
+            CopyRule::PreviousN => (card.saturating_sub(matches)..card).collect_vec(),
+            CopyRule::Wraparound => (1..=matches.min(len.saturating_sub(1)))
+                .map(|k| (card + k) % len)
+                .collect_vec(),
+            CopyRule::Capped(cap) => CopyRule::NextN.targets(card, matches.min(*cap), len),
         }
-        sum += cards_instances[i];
     }
-    sum
+}
+
+fn score(file_content: &str, rule: &ScoringRule) -> Result<usize> {
+    parse_cards(file_content)?
+        .iter()
+        .try_fold(0, |sum, c| rule.add_score(sum, c))
+}
+
+fn solve_task1(file_content: &str) -> Result<usize> {
+    score(file_content, &ScoringRule::Doubling)
+}
+
+/// What happened to one card while playing part 2.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct CardOutcome {
+    /// The card's ID, as written in the input.
+    id: usize,
+    matches: usize,
+    /// Instances of the card held at the end, the original included.
+    instances: usize,
+    /// Copies of later cards won by all instances of this card.
+    copies_won: usize,
+    /// IDs of the earlier cards that won copies of this one, with how many
+    /// each.
+    won_from: Vec<(usize, usize)>,
+}
+
+/// Full trace of a part 2 game. Cards are looked up by ID and scratched once
+/// each in input order, so copies won of a card that was already scratched
+/// are counted but don't win anything themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Scratchcards {
+    cards: Vec<CardOutcome>,
+}
+
+impl Scratchcards {
+    fn new(matches: Vec<usize>) -> Self {
+        Self::with_rule(matches, CopyRule::NextN)
+    }
+
+    /// Plays cards numbered from 1 with the given matches.
+    fn with_rule(matches: Vec<usize>, rule: CopyRule) -> Self {
+        Self::play((1..).zip(matches), rule)
+    }
+
+    /// Plays `(id, matches)` cards in order, their IDs following each other.
+    fn play(cards: impl IntoIterator<Item = (usize, usize)>, rule: CopyRule) -> Self {
+        let mut cards = cards
+            .into_iter()
+            .map(|(id, matches)| CardOutcome {
+                id,
+                matches,
+                instances: 1,
+                ..CardOutcome::default()
+            })
+            .collect_vec();
+        for i in 0..cards.len() {
+            let instances = cards[i].instances;
+            let targets = rule.targets(i, cards[i].matches, cards.len());
+            for &j in targets.iter() {
+                cards[j].instances += instances;
+                let id = cards[i].id;
+                cards[j].won_from.push((id, instances));
+            }
+            cards[i].copies_won = targets.len() * instances;
+        }
+        Self { cards }
+    }
+
+    fn from_input(file_content: &str, rule: CopyRule) -> Result<Self> {
+        let cards = parse_cards(file_content)?;
+        Ok(Self::play(cards.iter().map(|c| (c.id, c.matches())), rule))
+    }
+
+    fn card(&self, id: usize) -> Option<&CardOutcome> {
+        let first = self.cards.first()?.id;
+        self.cards
+            .get(id.checked_sub(first)?)
+            .filter(|c| c.id == id)
+    }
+
+    /// How many instances of card `id` are held at the end.
+    fn copies_of(&self, id: usize) -> Option<usize> {
+        self.card(id).map(|c| c.instances)
+    }
+
+    fn total(&self) -> usize {
+        self.cards.iter().map(|c| c.instances).sum()
+    }
+}
+
+fn solve_task2(file_content: &str) -> Result<usize> {
+    Ok(Scratchcards::from_input(file_content, CopyRule::NextN)?.total())
+}
+
+fn solve_task1_stream(reader: impl BufRead, report: impl FnMut(Progress)) -> Result<usize> {
+    let mut cards = CardReader::default();
+    let mut sum = 0;
+    stream::for_each_line(reader, report, |_, line| {
+        if let Some(card) = cards.read(line)? {
+            sum = ScoringRule::Doubling.add_score(sum, &card)?;
+        }
+        Ok(())
+    })?;
+    Ok(sum)
+}
+
+/// Streaming part 2: only the copies already won for the upcoming cards are
+/// kept, so memory is bounded by the largest match count rather than the
+/// number of cards.
+fn solve_task2_stream(reader: impl BufRead, report: impl FnMut(Progress)) -> Result<usize> {
+    let mut cards = CardReader::default();
+    let mut pending_copies: VecDeque<usize> = VecDeque::new();
+    let mut sum = 0;
+    stream::for_each_line(reader, report, |_, line| {
+        if let Some(card) = cards.read(line)? {
+            let matches = card.matches();
+            let instances = 1 + pending_copies.pop_front().unwrap_or_default();
+            if pending_copies.len() < matches {
+                pending_copies.resize(matches, 0);
+            }
+            for copies in pending_copies.iter_mut().take(matches) {
+                *copies += instances;
+            }
+            sum += instances;
+        }
+        Ok(())
+    })?;
+    Ok(sum)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use anyhow::Result;
     #[test]
     fn day4_part1() -> Result<()> {
-        let s = solve_task1(include_str!("input.txt"));
+        let s = solve_task1(include_str!("input.txt"))?;
         println!("{}", s);
         assert_eq!(s, 21558);
         Ok(())
@@ -68,9 +338,121 @@ mod test {
 
     #[test]
     fn day4_part2() -> Result<()> {
-        let s = solve_task2(include_str!("input.txt"));
+        let s = solve_task2(include_str!("input.txt"))?;
         println!("{}", s);
         assert_eq!(s, 10425665);
         Ok(())
     }
+
+    #[test]
+    fn day4_stream() -> Result<()> {
+        let input = "Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53
+Card 2: 13 32 20 16 61 | 61 30 68 82 17 32 24 19
+Card 3:  1 21 53 59 44 | 69 82 63 72 16 21 14  1
+Card 4: 41 92 73 84 69 | 59 84 76 51 58  5 54 83
+Card 5: 87 83 26 28 32 | 88 30 70 12 93 22 82 36
+Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11
+";
+        assert_eq!(solve_task1_stream(input.as_bytes(), |_| {})?, 13);
+        assert_eq!(solve_task2_stream(input.as_bytes(), |_| {})?, 30);
+        assert_eq!(solve_task2(input)?, 30);
+
+        let repeated = "Card 1: 1 2 | 2 2\n";
+        assert!(solve_task1(repeated).is_err());
+        assert!(solve_task1_stream(repeated.as_bytes(), |_| {}).is_err());
+        let gap = "Card 1: 1 | 1\nCard 3: 1 | 1\n";
+        assert!(solve_task2(gap).is_err());
+        assert!(solve_task2_stream(gap.as_bytes(), |_| {}).is_err());
+        assert!(solve_task1("Card 1 1 | 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn day4_trace() -> Result<()> {
+        let input = "Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53
+Card 2: 13 32 20 16 61 | 61 30 68 82 17 32 24 19
+Card 3:  1 21 53 59 44 | 69 82 63 72 16 21 14  1
+Card 4: 41 92 73 84 69 | 59 84 76 51 58  5 54 83
+Card 5: 87 83 26 28 32 | 88 30 70 12 93 22 82 36
+Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11";
+        let game = Scratchcards::from_input(input, CopyRule::NextN)?;
+        assert_eq!(game.total(), 30);
+        let copies = (1..=6).flat_map(|n| game.copies_of(n)).collect_vec();
+        assert_eq!(copies, vec![1, 2, 4, 8, 14, 1]);
+        assert_eq!(game.copies_of(7), None);
+
+        let card5 = game.card(5).unwrap();
+        assert_eq!(card5.won_from, vec![(1, 1), (3, 4), (4, 8)]);
+        assert_eq!(game.card(2).unwrap().copies_won, 4);
+        let won: usize = game.cards.iter().map(|c| c.copies_won).sum();
+        assert_eq!(won + game.cards.len(), game.total());
+
+        let offset = Scratchcards::from_input(
+            "Card 5: 1 2 | 1 2\nCard 6: 3 | 3\nCard 7: 4 | 5",
+            CopyRule::NextN,
+        )?;
+        assert_eq!(offset.copies_of(1), None);
+        assert_eq!(offset.copies_of(5), Some(1));
+        assert_eq!(offset.copies_of(7), Some(4));
+        assert_eq!(offset.card(7).unwrap().won_from, vec![(5, 1), (6, 2)]);
+        assert_eq!(offset.card(8), None);
+        Ok(())
+    }
+
+    #[test]
+    fn day4_rules() {
+        let points = |rule: ScoringRule| (0..6).flat_map(|m| rule.score(m)).collect_vec();
+        assert_eq!(points(ScoringRule::Doubling), vec![0, 1, 2, 4, 8, 16]);
+        assert_eq!(points(ScoringRule::Linear), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(points(ScoringRule::Fibonacci), vec![0, 1, 2, 3, 5, 8]);
+        assert_eq!(
+            points(ScoringRule::Custom(Box::new(|m| m * m))),
+            vec![0, 1, 4, 9, 16, 25]
+        );
+        assert_eq!(ScoringRule::Doubling.score(64), Some(1 << 63));
+        assert_eq!(ScoringRule::Doubling.score(65), None);
+        assert_eq!(ScoringRule::Fibonacci.score(92), Some(12200160415121876738));
+        assert_eq!(ScoringRule::Fibonacci.score(93), None);
+        let numbers = (1..=65).join(" ");
+        let card = format!("Card 1: {} | {}", numbers, numbers);
+        assert!(score(&card, &ScoringRule::Doubling).is_err());
+        assert_eq!(score(&card, &ScoringRule::Linear).ok(), Some(65));
+
+        assert_eq!(CopyRule::NextN.targets(3, 4, 5), vec![4]);
+        assert_eq!(CopyRule::PreviousN.targets(3, 4, 5), vec![0, 1, 2]);
+        assert_eq!(CopyRule::Wraparound.targets(3, 4, 5), vec![4, 0, 1, 2]);
+        assert_eq!(CopyRule::Wraparound.targets(0, 9, 3), vec![1, 2]);
+        assert_eq!(CopyRule::Capped(1).targets(0, 3, 5), vec![1]);
+
+        let matches = vec![4, 2, 2, 1, 0, 0];
+        assert_eq!(Scratchcards::new(matches.clone()).total(), 30);
+        let capped = Scratchcards::with_rule(matches, CopyRule::Capped(1));
+        assert_eq!(capped.total(), 6 + 1 + 2 + 3 + 4);
+        let wrapped = Scratchcards::with_rule(vec![1, 0, 0, 3], CopyRule::Wraparound);
+        let copies = (1..=4).flat_map(|n| wrapped.copies_of(n)).collect_vec();
+        assert_eq!(copies, vec![2, 3, 2, 1]);
+        assert_eq!(wrapped.card(1).unwrap().won_from, vec![(4, 1)]);
+    }
+
+    #[test]
+    fn day4_cards() -> Result<()> {
+        let card = Card::new("Card  12: 41 48 83 86 17 | 83 86  6 31 17  9 48 53")?;
+        assert_eq!(card.id, 12);
+        assert_eq!(card.winning, vec![41, 48, 83, 86, 17]);
+        assert_eq!(card.matches(), 4);
+        let set = |numbers: &[u32]| NumberSet::new(numbers).unwrap();
+        assert_eq!(set(&[1, 64, 200]).intersection_len(&set(&[64, 200, 3])), 2);
+        assert_eq!(NumberSet::new(&[4_000_000_000]), None);
+        let huge = Card::new("Card 1: 4000000000 7 | 7 4000000000 12")?;
+        assert_eq!(huge.matches(), 2);
+
+        let cards = parse_cards("Card 1: 1 2 | 2 3\nCard 2: 4 | 4\n")?;
+        assert_eq!(cards.iter().map(|c| c.matches()).collect_vec(), vec![1, 1]);
+        assert!(parse_cards("Card 1: 1 2 | 2 3\nCard 3: 4 | 4").is_err());
+        assert!(parse_cards("Card 1: 1 1 | 2 3").is_err());
+        assert!(parse_cards("Card 1: 1 2 | 3 3").is_err());
+        assert!(Card::new("Card x: 1 | 2").is_err());
+        assert!(Card::new("Card 1: 1 x | 2").is_err());
+        Ok(())
+    }
 }
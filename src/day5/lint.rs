@@ -0,0 +1,241 @@
+use super::{leftover, parse_stage, task1_seeds, TargetRange};
+use crate::interval_map::{IntervalMap, Segment};
+use anyhow::Result;
+use itertools::Itertools;
+use nom::{character::complete::newline, combinator::opt, multi::separated_list1, sequence::tuple};
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+/// Something suspicious about the ranges of one almanac stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Issue {
+    /// Two ranges claim the same source values, so `get` is ambiguous.
+    OverlappingSources(Range<u64>, Range<u64>),
+    /// Two ranges map onto the same values, so the stage isn't injective and
+    /// `reverse_get` is ambiguous.
+    OverlappingDestinations(Range<u64>, Range<u64>),
+    /// A range of length 0, which maps nothing.
+    ZeroLength { src: u64, dst: u64 },
+    /// Source values below or between the ranges that no range maps, left as
+    /// identity. The same ranges as [`IntervalMap::gaps`].
+    Gap(Range<u64>),
+    /// A line that isn't part of any stage, e.g. a range overflowing `u64`.
+    /// Nothing after it was read.
+    Unreadable { line: usize, text: String },
+}
+
+impl Issue {
+    fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Issue::OverlappingSources(..)
+                | Issue::OverlappingDestinations(..)
+                | Issue::Unreadable { .. }
+        )
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::OverlappingSources(a, b) => write!(f, "overlapping sources {:?} and {:?}", a, b),
+            Issue::OverlappingDestinations(a, b) => {
+                write!(f, "overlapping destinations {:?} and {:?}", a, b)
+            }
+            Issue::ZeroLength { src, dst } => write!(f, "zero length range {} -> {}", src, dst),
+            Issue::Gap(r) => write!(f, "unmapped gap {:?}", r),
+            Issue::Unreadable { line, text } => write!(f, "unreadable line {}: {:?}", line, text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StageReport {
+    source: String,
+    destination: String,
+    issues: Vec<Issue>,
+}
+
+/// Lint report over every stage of an almanac, printed one issue per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Report {
+    stages: Vec<StageReport>,
+    /// Issues outside any stage.
+    almanac: Vec<Issue>,
+}
+
+impl Report {
+    fn has_errors(&self) -> bool {
+        self.issues().any(|i| i.is_error())
+    }
+
+    fn issues(&self) -> impl Iterator<Item = &Issue> + '_ {
+        self.stages
+            .iter()
+            .flat_map(|s| s.issues.iter())
+            .chain(self.almanac.iter())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for s in self.stages.iter() {
+            for i in s.issues.iter() {
+                let level = if i.is_error() { "error" } else { "warning" };
+                writeln!(f, "{}-to-{}: {}: {}", s.source, s.destination, level, i)?;
+            }
+        }
+        for i in self.almanac.iter() {
+            let level = if i.is_error() { "error" } else { "warning" };
+            writeln!(f, "almanac: {}: {}", level, i)?;
+        }
+        let count = self.issues().count();
+        if count == 0 {
+            writeln!(f, "no issues")
+        } else {
+            writeln!(f, "{} issue(s)", count)
+        }
+    }
+}
+
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+fn check_stage(source: &str, destination: &str, ranges: &[TargetRange]) -> Result<StageReport> {
+    let mut issues = ranges
+        .iter()
+        .filter(|r| r.length == 0)
+        .map(|r| Issue::ZeroLength {
+            src: r.src,
+            dst: r.dst,
+        })
+        .collect_vec();
+
+    let segments = ranges
+        .iter()
+        .filter(|r| r.length > 0)
+        .map(|r| r.segment())
+        .sorted_by_key(|s| s.src)
+        .collect_vec();
+    for (a, b) in segments.iter().tuple_combinations() {
+        if overlaps(&a.src_range(), &b.src_range()) {
+            issues.push(Issue::OverlappingSources(a.src_range(), b.src_range()));
+        }
+        if overlaps(&a.dst_range(), &b.dst_range()) {
+            issues.push(Issue::OverlappingDestinations(a.dst_range(), b.dst_range()));
+        }
+    }
+
+    // overlapping sources are merged so the covered values fit in an
+    // `IntervalMap`, whose gaps are then the unmapped values
+    let mut covered: Vec<Range<u64>> = Vec::new();
+    for s in segments.iter() {
+        match covered.last_mut() {
+            Some(last) if last.end >= s.src => last.end = last.end.max(s.src + s.length),
+            _ => covered.push(s.src_range()),
+        }
+    }
+    let coverage = IntervalMap::from_segments(covered.iter().map(|r| Segment {
+        src: r.start,
+        dst: r.start,
+        length: r.end - r.start,
+    }))?;
+    issues.extend(coverage.gaps().map(Issue::Gap));
+
+    Ok(StageReport {
+        source: source.to_string(),
+        destination: destination.to_string(),
+        issues,
+    })
+}
+
+/// Checks every stage of an almanac, with or without its "seeds:" line,
+/// without rejecting the inconsistencies `Mappings::new` refuses. Reading
+/// stops at the first line that isn't part of a stage, which is reported.
+fn lint(input: &str) -> Result<Report> {
+    let (stages_input, _) = opt(task1_seeds)(input).map_err(|e| e.to_owned())?;
+    let (rest, stages) = separated_list1(tuple((newline, newline)), parse_stage)(stages_input)
+        .map_err(|e| e.to_owned())?;
+    Ok(Report {
+        stages: stages
+            .iter()
+            .map(|(source, destination, ranges)| check_stage(source, destination, ranges))
+            .collect::<Result<Vec<_>>>()?,
+        almanac: leftover(input, rest)
+            .map(|(line, text)| Issue::Unreadable {
+                line,
+                text: text.to_string(),
+            })
+            .into_iter()
+            .collect_vec(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn day5_lint() -> Result<()> {
+        let clean = lint("seeds: 1 2\n\nseed-to-soil map:\n50 98 2\n52 50 48\n0 0 50")?;
+        assert!(clean.issues().next().is_none());
+        let example = lint("seed-to-soil map:\n50 98 2\n52 50 48")?;
+        assert_eq!(example.stages[0].issues, vec![Issue::Gap(0..50)]);
+        let map =
+            IntervalMap::from_segments([Segment::new(98, 50, 2)?, Segment::new(50, 52, 48)?])?;
+        assert_eq!(
+            map.gaps().map(Issue::Gap).collect_vec(),
+            example.stages[0].issues
+        );
+        assert_eq!(clean.to_string(), "no issues\n");
+
+        let report = lint(
+            "seed-to-soil map:
+0 10 5
+100 12 5
+0 30 2
+7 40 0
+
+soil-to-water map:
+1 2 3",
+        )?;
+        assert!(report.has_errors());
+        assert_eq!(
+            report.stages[0].issues,
+            vec![
+                Issue::ZeroLength { src: 40, dst: 7 },
+                Issue::OverlappingSources(10..15, 12..17),
+                Issue::OverlappingDestinations(0..5, 0..2),
+                Issue::Gap(0..10),
+                Issue::Gap(17..30),
+            ]
+        );
+        assert_eq!(report.stages[1].issues, vec![Issue::Gap(0..2)]);
+        assert_eq!(
+            report.to_string().lines().nth(1),
+            Some("seed-to-soil: error: overlapping sources 10..15 and 12..17")
+        );
+
+        let truncated = lint(
+            "seed-to-soil map:
+0 18446744073709551610 6
+
+soil-to-location map:
+100 0 10",
+        )?;
+        assert!(truncated.has_errors());
+        assert_eq!(truncated.stages.len(), 1);
+        assert_eq!(
+            truncated.almanac,
+            vec![Issue::Unreadable {
+                line: 2,
+                text: "0 18446744073709551610 6".to_string()
+            }]
+        );
+        assert!(truncated
+            .to_string()
+            .starts_with("almanac: error: unreadable line 2"));
+        Ok(())
+    }
+}
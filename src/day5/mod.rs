@@ -1,26 +1,34 @@
-use anyhow::Result;
+mod lint;
+
+use crate::interval_map::{IntervalMap, Segment};
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
 use nom::sequence::separated_pair;
 use nom::{
     bytes::complete::tag,
-    character::complete::{self, newline, not_line_ending, space1},
-    combinator::map,
+    character::complete::{self, alpha1, newline, space1},
+    combinator::{map_opt, map_res, verify},
     multi::{separated_list0, separated_list1},
-    sequence::{delimited, preceded, terminated, tuple},
+    sequence::{delimited, terminated, tuple},
     IResult,
 };
 use std::ops::Range;
 
+/// One "<dst> <src> <length>" line of a map, as written in the almanac.
 #[derive(Debug, Clone)]
 struct TargetRange {
-    src: usize,
-    length: usize,
-    dst: usize,
+    src: u64,
+    length: u64,
+    dst: u64,
 }
 
+/// One "<source>-to-<destination> map:" stage of the almanac.
 #[derive(Debug, Clone)]
 struct ComponentRanges {
-    // sorted in src ascending order
-    ranges: Vec<TargetRange>,
+    source: String,
+    destination: String,
+    map: IntervalMap,
+    inverse: IntervalMap,
 }
 
 #[derive(Debug, Clone)]
@@ -29,142 +37,275 @@ struct Mappings {
 }
 
 impl TargetRange {
+    /// Parses "<dst> <src> <length>", rejecting ranges whose end doesn't fit
+    /// in a `u64` on either side, so mapping a value can never overflow.
     fn new(input: &str) -> IResult<&str, Self> {
-        let (input, (dst, src, length)) = tuple((
-            (terminated(complete::u32, space1)),
-            (terminated(complete::u32, space1)),
-            complete::u32,
-        ))(input)?;
-        Ok((
-            input,
-            Self {
-                src: src as usize,
-                dst: dst as usize,
-                length: length as usize,
-            },
-        ))
+        let (input, (dst, src, length)) = verify(
+            tuple((
+                (terminated(complete::u64, space1)),
+                (terminated(complete::u64, space1)),
+                complete::u64,
+            )),
+            |(dst, src, length): &(u64, u64, u64)| Segment::new(*src, *dst, *length).is_ok(),
+        )(input)?;
+        Ok((input, Self { src, dst, length }))
+    }
+
+    fn segment(&self) -> Segment {
+        Segment {
+            src: self.src,
+            dst: self.dst,
+            length: self.length,
+        }
     }
+}
 
-    fn contains_src(&self, src: usize) -> bool {
-        self.src <= src && src < self.src + self.length
+/// The source and destination categories and the ranges of one map, as
+/// written and without any check.
+fn parse_stage(input: &str) -> IResult<&str, (&str, &str, Vec<TargetRange>)> {
+    let (input, (source, destination)) = terminated(
+        separated_pair(alpha1, tag("-to-"), alpha1),
+        tuple((tag(" map:"), newline)),
+    )(input)?;
+    let (input, ranges) = separated_list0(newline, TargetRange::new)(input)?;
+    Ok((input, (source, destination, ranges)))
+}
+
+impl ComponentRanges {
+    /// Parses the header and ranges of one map. Ranges overlapping on their
+    /// source or destination are rejected since they'd make `get` or
+    /// `reverse_get` ambiguous.
+    fn new(input: &str) -> IResult<&str, Self> {
+        map_res(parse_stage, |(source, destination, ranges)| {
+            Self::from_ranges(source, destination, &ranges)
+        })(input)
     }
-    fn map(&self, src: usize) -> usize {
-        self.dst + src - self.src
+
+    fn from_ranges(source: &str, destination: &str, ranges: &[TargetRange]) -> Result<Self> {
+        Self::from_map(
+            source,
+            destination,
+            IntervalMap::from_segments(ranges.iter().map(|r| r.segment()))?,
+        )
     }
 
-    fn reverse_map(&self, dst: usize) -> usize {
-        self.src + dst - self.dst
+    fn from_map(source: &str, destination: &str, map: IntervalMap) -> Result<Self> {
+        let inverse = map.invert()?;
+        Ok(Self {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            map,
+            inverse,
+        })
     }
 
-    fn contains_dst(&self, dst: usize) -> bool {
-        self.dst <= dst && dst < self.dst + self.length
+    fn get(&self, src: u64) -> u64 {
+        self.map.get(src)
+    }
+    fn reverse_get(&self, dst: u64) -> u64 {
+        self.inverse.get(dst)
     }
 }
 
-impl ComponentRanges {
+impl Mappings {
+    /// Parses every stage, checking each one starts from the category the
+    /// previous one ends at.
     fn new(input: &str) -> IResult<&str, Self> {
-        let (i, mut ranges) = separated_list0(newline, TargetRange::new)(input)?;
-        ranges.sort_by_key(|r| r.src);
-        Ok((i, Self { ranges }))
+        map_res(
+            separated_list1(tuple((newline, newline)), ComponentRanges::new),
+            Self::from_stages,
+        )(input)
     }
 
-    fn get(&self, src: usize) -> usize {
-        match self.ranges.iter().find(|r| r.contains_src(src)) {
-            Some(r) => r.map(src),
-            None => src,
+    fn from_stages(maps: Vec<ComponentRanges>) -> Result<Self> {
+        for (a, b) in maps.iter().tuple_windows() {
+            if a.destination != b.source {
+                bail!(
+                    "{}-to-{} map is followed by {}-to-{} map",
+                    a.source,
+                    a.destination,
+                    b.source,
+                    b.destination
+                );
+            }
         }
+        Ok(Self { maps })
     }
-    fn ranges_srcs(&self) -> impl Iterator<Item = usize> + '_ {
-        std::iter::once(0).chain(self.ranges.iter().map(|r| r.src))
-    }
-    fn reverse_get(&self, dst: usize) -> usize {
-        match self.ranges.iter().find(|r| r.contains_dst(dst)) {
-            Some(r) => r.reverse_map(dst),
-            None => dst,
+
+    /// The stages leading from category `from` to category `to`, empty when
+    /// both are the same.
+    fn stages(&self, from: &str, to: &str) -> Result<&[ComponentRanges]> {
+        let categories = self
+            .maps
+            .iter()
+            .map(|m| m.source.as_str())
+            .chain(self.maps.last().map(|m| m.destination.as_str()))
+            .collect_vec();
+        let position = |name: &str| {
+            categories
+                .iter()
+                .position(|c| *c == name)
+                .ok_or_else(|| anyhow!("unknown category {}", name))
+        };
+        let (start, end) = (position(from)?, position(to)?);
+        if start > end {
+            bail!("{} comes after {} in the almanac", from, to);
         }
+        Ok(&self.maps[start..end])
     }
-}
 
-impl Mappings {
-    fn new(input: &str) -> IResult<&str, Self> {
-        let (i, maps) = separated_list1(
-            tuple((newline, newline)),
-            preceded(tuple((not_line_ending, newline)), ComponentRanges::new),
-        )(input)?;
-        Ok((i, Self { maps }))
+    /// Maps `value` of category `from` to category `to`, e.g. soil 42 to
+    /// humidity.
+    fn convert(&self, from: &str, to: &str, value: u64) -> Result<u64> {
+        Ok(self.stages(from, to)?.iter().fold(value, |v, m| m.get(v)))
     }
 
-    fn get(&self, seed: usize) -> usize {
+    /// Every value of category `from` that ends up in `range` of category
+    /// `to`, e.g. which seeds end at location 100.
+    fn preimage(&self, from: &str, to: &str, range: Range<u64>) -> Result<Vec<Range<u64>>> {
+        let mut ranges = vec![range];
+        for m in self.stages(from, to)?.iter().rev() {
+            ranges = ranges
+                .into_iter()
+                .flat_map(|r| m.map.preimage(r))
+                .sorted_by_key(|r| r.start)
+                .collect_vec();
+        }
+        Ok(ranges)
+    }
+
+    fn get(&self, seed: u64) -> u64 {
         self.maps.iter().fold(seed, |v, m| m.get(v))
     }
 
-    fn get_src(&self, dst: usize, applied_levels: usize) -> usize {
-        let mut value = dst;
-        for m in self.maps.iter().take(applied_levels).rev() {
-            value = m.reverse_get(value);
-        }
-        value
+    /// A single map equivalent to applying every stage in turn.
+    fn compose(&self) -> IntervalMap {
+        self.maps
+            .iter()
+            .fold(IntervalMap::new(), |acc, m| acc.compose(&m.map))
     }
 
-    fn get_from_level(&self, src: usize, already_applied: usize) -> usize {
-        self.maps[already_applied..]
+    /// Maps every range of `seeds` through all the stages, splitting them
+    /// wherever a stage's ranges start or end, so the result is exactly the
+    /// set of locations reachable from those seeds.
+    fn map_ranges(&self, seeds: &[Range<u64>]) -> Vec<MappedRange> {
+        let mut mapped = seeds
             .iter()
-            .fold(src, |v, m| m.get(v))
+            .map(|r| MappedRange {
+                source: r.clone(),
+                target: r.clone(),
+            })
+            .collect_vec();
+        for m in self.maps.iter() {
+            mapped = mapped
+                .into_iter()
+                .flat_map(|r| {
+                    m.map
+                        .map_range(r.target.clone())
+                        .into_iter()
+                        .map(move |(src, dst)| {
+                            let start = r.source.start + (src.start - r.target.start);
+                            MappedRange {
+                                source: start..start + (src.end - src.start),
+                                target: dst,
+                            }
+                        })
+                })
+                .collect_vec();
+        }
+        mapped
     }
+}
 
-    fn enumerate(&self) -> impl Iterator<Item = (usize, &ComponentRanges)> + '_ {
-        self.maps.iter().enumerate()
+/// Two chains are equal when they map every value the same way, whatever
+/// stages they are made of.
+impl PartialEq for Mappings {
+    fn eq(&self, other: &Self) -> bool {
+        self.compose() == other.compose()
     }
 }
 
-fn task1_seeds(data: &str) -> IResult<&str, Vec<usize>> {
+/// A range of values reached at the end of a `Mappings` chain, with the input
+/// range it came from. Both ranges have the same length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MappedRange {
+    source: Range<u64>,
+    target: Range<u64>,
+}
+
+fn task1_seeds(data: &str) -> IResult<&str, Vec<u64>> {
     delimited(
         tag("seeds: "),
-        separated_list1(space1, map(complete::u32, |x| x as usize)),
+        separated_list1(space1, complete::u64),
         tuple((newline, newline)),
     )(data)
 }
 
-fn task2_seeds(data: &str) -> IResult<&str, Vec<Range<usize>>> {
+fn task2_seeds(data: &str) -> IResult<&str, Vec<Range<u64>>> {
     delimited(
         tag("seeds: "),
         separated_list1(
             space1,
-            map(
-                separated_pair(complete::u32, space1, complete::u32),
-                |(x, y)| (x as usize)..((x + y) as usize),
+            map_opt(
+                separated_pair(complete::u64, space1, complete::u64),
+                |(x, y)| Some(x..x.checked_add(y)?),
             ),
         ),
         tuple((newline, newline)),
     )(data)
 }
-pub fn solve_task1() -> Result<usize> {
-    let data = include_str!("input.txt");
-    let (i, seeds) = task1_seeds(data).expect("failed to parse inputs");
-    let (_, components) = Mappings::new(i)?;
-    Ok(seeds.into_iter().map(|s| components.get(s)).min().unwrap())
-}
-
-pub fn solve_task2() -> Result<usize> {
-    let data = include_str!("input.txt");
-    let (i, seeds) = task2_seeds(data).expect("failed to parse inputs");
-    let (_, components) = Mappings::new(i)?;
-
-    Ok(components
-        .enumerate()
-        .map(|(idx, range)| {
-            range
-                .ranges_srcs()
-                .filter(|&start| {
-                    let src = components.get_src(start, idx);
-                    seeds.iter().any(|r| r.contains(&src))
-                })
-                .map(|start| components.get_from_level(start, idx))
-                .min()
-                .unwrap()
-        })
+
+/// The first line of `rest`, where a parser of `input` stopped, with its line
+/// number counted from 1. `None` when only blank lines are left.
+fn leftover<'a>(input: &str, rest: &'a str) -> Option<(usize, &'a str)> {
+    let rest = rest.trim_start_matches(['\r', '\n']);
+    if rest.trim().is_empty() {
+        return None;
+    }
+    let line = input[..input.len() - rest.len()].matches('\n').count() + 1;
+    Some((line, rest.lines().next().unwrap_or_default()))
+}
+
+/// Parses the stages of `data` starting at `stages`, failing on any line
+/// left unread, such as a range overflowing `u64`, rather than silently
+/// dropping the stages after it.
+fn parse_mappings(data: &str, stages: &str) -> Result<Mappings> {
+    let (rest, mappings) = Mappings::new(stages).map_err(|e| e.to_owned())?;
+    if let Some((line, text)) = leftover(data, rest) {
+        bail!("can't read line {} of the almanac: {:?}", line, text);
+    }
+    Ok(mappings)
+}
+
+fn lowest_location(data: &str) -> Result<u64> {
+    let (i, seeds) = task1_seeds(data).map_err(|e| e.to_owned())?;
+    let components = parse_mappings(data, i)?;
+    let seed_to_location = components.compose();
+    seeds
+        .into_iter()
+        .map(|s| seed_to_location.get(s))
+        .min()
+        .ok_or_else(|| anyhow!("no seeds"))
+}
+
+fn lowest_location_of_ranges(data: &str) -> Result<u64> {
+    let (i, seeds) = task2_seeds(data).map_err(|e| e.to_owned())?;
+    let components = parse_mappings(data, i)?;
+
+    components
+        .map_ranges(&seeds)
+        .into_iter()
+        .map(|r| r.target.start)
         .min()
-        .unwrap())
+        .ok_or_else(|| anyhow!("no seed maps to a location"))
+}
+
+pub fn solve_task1() -> Result<u64> {
+    lowest_location(include_str!("input.txt"))
+}
+
+pub fn solve_task2() -> Result<u64> {
+    lowest_location_of_ranges(include_str!("input.txt"))
 }
 
 #[cfg(test)]
@@ -187,4 +328,167 @@ mod tests {
         assert_eq!(result, 100165128);
         Ok(())
     }
+
+    const EXAMPLE: &str = "seeds: 79 14 55 13
+
+seed-to-soil map:
+50 98 2
+52 50 48
+
+soil-to-fertilizer map:
+0 15 37
+37 52 2
+39 0 15
+
+fertilizer-to-water map:
+49 53 8
+0 11 42
+42 0 7
+57 7 4
+
+water-to-light map:
+88 18 7
+18 25 70
+
+light-to-temperature map:
+45 77 23
+81 45 19
+68 64 13
+
+temperature-to-humidity map:
+0 69 1
+1 0 69
+
+humidity-to-location map:
+60 56 37
+56 93 4";
+
+    #[test]
+    fn day5_example() -> Result<()> {
+        assert_eq!(lowest_location(EXAMPLE)?, 35);
+        assert_eq!(lowest_location_of_ranges(EXAMPLE)?, 46);
+        assert_eq!(lowest_location(&format!("{}\n\n", EXAMPLE))?, 35);
+
+        let almanac = |seeds: &str, range: &str| {
+            format!(
+                "seeds: {}\n\nseed-to-soil map:\n{}\n\nsoil-to-location map:\n100 0 10",
+                seeds, range
+            )
+        };
+        let (fits, overflows) = ("0 18446744073709551600 6", "0 18446744073709551610 6");
+        assert_eq!(lowest_location(&almanac("5", fits))?, 105);
+        let error = lowest_location(&almanac("5", overflows))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("line 4"), "{}", error);
+        assert_eq!(lowest_location_of_ranges(&almanac("5 1", fits))?, 105);
+        assert!(lowest_location_of_ranges(&almanac("5 1", overflows)).is_err());
+        assert!(lowest_location(&almanac("5", "4 5 x")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn day5_large_values() -> Result<()> {
+        let max = u64::MAX;
+        let (_, r) = TargetRange::new(&format!("0 {} 5", max - 5)).map_err(|e| e.to_owned())?;
+        let c = ComponentRanges::from_ranges("seed", "soil", &[r])?;
+        assert_eq!(c.get(max - 1), 4);
+        assert_eq!(c.get(max), max);
+        assert_eq!(c.reverse_get(4), max - 1);
+        assert!(TargetRange::new(&format!("0 {} 6", max - 5)).is_err());
+        assert!(TargetRange::new(&format!("{} 0 6", max - 5)).is_err());
+
+        let input = format!(
+            "seeds: {} 3 10 {}\n\nseed-to-soil map:\n10 {} 4\n",
+            max - 3,
+            max - 10,
+            max - 4
+        );
+        let (i, seeds) = task2_seeds(&input).map_err(|e| e.to_owned())?;
+        assert_eq!(seeds, vec![(max - 3)..max, 10..max]);
+        let (_, m) = Mappings::new(i).map_err(|e| e.to_owned())?;
+        assert_eq!(m.get(max - 1), 13);
+        assert_eq!(m.get(max), max);
+        assert!(task2_seeds(&format!("seeds: {} 2\n\n", max - 1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn day5_range_propagation() -> Result<()> {
+        let (i, seeds) = task2_seeds(EXAMPLE).map_err(|e| e.to_owned())?;
+        let (_, m) = Mappings::new(i).map_err(|e| e.to_owned())?;
+        let mapped = m.map_ranges(&seeds);
+
+        let count: u64 = mapped.iter().map(|r| r.target.end - r.target.start).sum();
+        assert_eq!(count, 14 + 13);
+        for r in mapped.iter() {
+            assert!(seeds.iter().any(|s| s.contains(&r.source.start)));
+            for (seed, location) in r.source.clone().zip(r.target.clone()) {
+                assert_eq!(m.get(seed), location);
+            }
+        }
+        let lowest = mapped.iter().min_by_key(|r| r.target.start).unwrap();
+        assert_eq!((lowest.source.start, lowest.target.start), (82, 46));
+        let highest = mapped.iter().map(|r| r.target.end - 1).max();
+        assert_eq!(
+            highest,
+            seeds.iter().flat_map(|s| s.clone()).map(|s| m.get(s)).max()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn day5_compose() -> Result<()> {
+        let (i, _) = task1_seeds(EXAMPLE).map_err(|e| e.to_owned())?;
+        let (_, m) = Mappings::new(i).map_err(|e| e.to_owned())?;
+        let composed = m.compose();
+        assert!((0..200).all(|v| composed.get(v) == m.get(v)));
+
+        let flat = Mappings {
+            maps: vec![ComponentRanges::from_map("seed", "location", composed)?],
+        };
+        assert_eq!(flat, m);
+        let shorter = Mappings {
+            maps: m.maps[1..].to_vec(),
+        };
+        assert_ne!(shorter, m);
+        Ok(())
+    }
+
+    #[test]
+    fn day5_named_stages() -> Result<()> {
+        let (i, _) = task1_seeds(EXAMPLE).map_err(|e| e.to_owned())?;
+        let (_, m) = Mappings::new(i).map_err(|e| e.to_owned())?;
+        assert_eq!(m.maps[2].source, "fertilizer");
+        assert_eq!(m.maps[2].destination, "water");
+
+        assert_eq!(m.convert("seed", "location", 79)?, 82);
+        assert_eq!(m.convert("seed", "soil", 79)?, 81);
+        assert_eq!(m.convert("soil", "humidity", 81)?, 78);
+        assert_eq!(m.convert("water", "water", 5)?, 5);
+        assert!(m.convert("humidity", "soil", 1).is_err());
+        assert!(m.convert("seed", "mineral", 1).is_err());
+
+        assert_eq!(m.preimage("seed", "location", 82..83)?, vec![79..80]);
+        for r in m.preimage("seed", "location", 40..60)? {
+            assert!(r.clone().all(|s| (40..60).contains(&m.get(s))));
+        }
+        let count: u64 = m
+            .preimage("seed", "location", 40..60)?
+            .iter()
+            .map(|r| r.end - r.start)
+            .sum();
+        assert_eq!(count, 20);
+
+        let shuffled = "a-to-b map:\n1 2 3\n\nc-to-d map:\n1 2 3";
+        assert!(Mappings::new(shuffled).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn day5_overlapping_ranges() {
+        assert!(ComponentRanges::new("a-to-b map:\n50 98 2\n52 50 48").is_ok());
+        assert!(ComponentRanges::new("a-to-b map:\n50 98 2\n52 90 48").is_err());
+        assert!(ComponentRanges::new("a-to-b map:\n50 98 2\n51 0 10").is_err());
+    }
 }
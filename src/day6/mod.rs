@@ -1,80 +1,237 @@
 use anyhow::Result;
 use itertools::Itertools;
-use nom::bytes::complete::tag;
-use nom::character::complete;
-use nom::character::complete::{multispace0, newline};
-use nom::multi::separated_list1;
-use nom::sequence::{delimited, tuple};
-use nom::{combinator, IResult};
+use nom::character::complete::{alpha1, char, digit1, space0, space1};
+use nom::combinator::eof;
+use nom::multi::separated_list0;
+use nom::sequence::{delimited, separated_pair, tuple};
+use nom::IResult;
+use num::integer::Roots;
+use num::Integer;
+use std::fmt::{self, Display, Formatter};
+use std::num::ParseIntError;
+use std::ops::RangeInclusive;
 
+/// A race lasting `time` ms whose record is `distance` mm. Sheets are read
+/// as `u64` and widened to `u128` by default so `time²` never overflows, and
+/// any `num` integer such as `BigUint` works for larger races.
 #[derive(Clone, Debug)]
-struct Game {
-    time: u64,
-    distance: u64,
+struct Game<N = u128> {
+    time: N,
+    distance: N,
 }
 
-impl Game {
-    fn new(time: u64, distance: u64) -> Self {
+impl<N: Integer + Roots + Clone> Game<N> {
+    fn new(time: N, distance: N) -> Self {
         Self { time, distance }
     }
 
-    fn possibilities(&self) -> usize {
-        (0..self.time)
-            .filter_map(|p| {
-                if (self.time - p) * p > self.distance {
-                    Some(1)
-                } else {
-                    None
-                }
-            })
-            .count()
+    fn beats_record(&self, hold: &N) -> bool {
+        hold.clone() * (self.time.clone() - hold.clone()) > self.distance
     }
+
+    /// The hold times that beat the record. They lie strictly between the
+    /// roots of `p² - time·p + distance`, found with an integer square root
+    /// and then nudged so no float rounding can creep in.
+    fn winning_interval(&self) -> Option<RangeInclusive<N>> {
+        let two = N::one() + N::one();
+        let four = two.clone() * two.clone();
+        let square = self.time.clone() * self.time.clone();
+        let limit = four * self.distance.clone();
+        if square <= limit {
+            return None;
+        }
+        let root = (square - limit).sqrt();
+
+        let mut low = if root >= self.time {
+            N::zero()
+        } else {
+            (self.time.clone() - root) / two.clone()
+        };
+        while low > N::zero() && self.beats_record(&(low.clone() - N::one())) {
+            low = low - N::one();
+        }
+        while low <= self.time && !self.beats_record(&low) {
+            low = low + N::one();
+        }
+        // the product is symmetric around time / 2
+        if low.clone() * two > self.time {
+            return None;
+        }
+        let high = self.time.clone() - low.clone();
+        Some(low..=high)
+    }
+
+    fn possibilities(&self) -> N {
+        match self.winning_interval() {
+            Some(r) => r.end().clone() - r.start().clone() + N::one(),
+            None => N::zero(),
+        }
+    }
+}
+
+/// How the numbers of a `Time:` or `Distance:` row are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reading {
+    /// Each number is a separate race (part 1).
+    Races,
+    /// The spaces are bad kerning: all digits make a single race (part 2).
+    Kerned,
+}
+
+impl Reading {
+    /// Fails on numbers beyond `u64`, whose square wouldn't fit a `u128`.
+    fn numbers(&self, digits: Vec<&str>) -> Result<Vec<u64>, ParseIntError> {
+        match self {
+            Reading::Races => digits.into_iter().map(|d| d.parse()).collect(),
+            Reading::Kerned => Ok(vec![digits.concat().parse()?]),
+        }
+    }
+}
+
+/// Why a race sheet couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RaceParseError {
+    /// A line isn't "<label>: <numbers>".
+    Syntax {
+        line: usize,
+    },
+    Number {
+        label: String,
+        error: ParseIntError,
+    },
+    DuplicateRow(String),
+    MissingRow(&'static str),
+    /// A row doesn't have as many numbers as the `Time` row.
+    RowLength {
+        label: String,
+        expected: usize,
+        found: usize,
+    },
 }
 
-fn parse_games(input: &str) -> IResult<&str, Vec<Game>> {
-    let (input, times) = delimited(
-        tuple((tag("Time:"), multispace0)),
-        separated_list1(multispace0, combinator::map(complete::u32, |v| v as u64)),
-        newline,
-    )(input)
-    .map_err(|err| {
-        println!("error parsing times: {}", err);
-        err
-    })?;
-
-    let (input, distances) = delimited(
-        tuple((tag("Distance:"), multispace0)),
-        separated_list1(multispace0, combinator::map(complete::u32, |v| v as u64)),
-        newline,
-    )(input)
-    .map_err(|err| {
-        println!("error parsing distance: {}", err);
-        err
-    })?;
-
-    Ok((
-        input,
-        (0..times.len())
-            .map(|i| Game::new(times[i], distances[i]))
-            .collect_vec(),
-    ))
+impl Display for RaceParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RaceParseError::Syntax { line } => write!(f, "line {} is not a labelled row", line),
+            RaceParseError::Number { label, error } => {
+                write!(f, "bad number in {} row: {}", label, error)
+            }
+            RaceParseError::DuplicateRow(label) => write!(f, "{} row appears twice", label),
+            RaceParseError::MissingRow(label) => write!(f, "missing {} row", label),
+            RaceParseError::RowLength {
+                label,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} row has {} values but there are {} times",
+                label, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RaceParseError {}
+
+/// "<label>: <numbers>" with any amount of spaces or tabs around the parts.
+fn parse_row(line: &str) -> IResult<&str, (&str, Vec<&str>)> {
+    delimited(
+        space0,
+        separated_pair(
+            alpha1,
+            tuple((space0, char(':'), space0)),
+            separated_list0(space1, digit1),
+        ),
+        tuple((space0, eof)),
+    )(line)
+}
+
+/// Every labelled row of a race sheet, in input order. `Time` and `Distance`
+/// are required; other rows are kept so the format can grow new columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RaceSheet {
+    rows: Vec<(String, Vec<u64>)>,
 }
 
-fn solve_part1() -> Result<usize> {
-    let data = include_str!("input.txt");
-    let (_, games) = parse_games(data)?;
+impl RaceSheet {
+    fn new(input: &str, reading: Reading) -> Result<Self, RaceParseError> {
+        let mut rows: Vec<(String, Vec<u64>)> = Vec::new();
+        for (n, line) in input.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (_, (label, digits)) =
+                parse_row(line).map_err(|_| RaceParseError::Syntax { line: n + 1 })?;
+            if rows.iter().any(|(l, _)| l == label) {
+                return Err(RaceParseError::DuplicateRow(label.to_string()));
+            }
+            let numbers = reading
+                .numbers(digits)
+                .map_err(|error| RaceParseError::Number {
+                    label: label.to_string(),
+                    error,
+                })?;
+            rows.push((label.to_string(), numbers));
+        }
+
+        let sheet = Self { rows };
+        let expected = sheet.required("Time")?.len();
+        sheet.required("Distance")?;
+        for (label, numbers) in sheet.rows.iter() {
+            if numbers.len() != expected {
+                return Err(RaceParseError::RowLength {
+                    label: label.clone(),
+                    expected,
+                    found: numbers.len(),
+                });
+            }
+        }
+        Ok(sheet)
+    }
+
+    fn row(&self, label: &str) -> Option<&[u64]> {
+        self.rows
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|(_, numbers)| numbers.as_slice())
+    }
+
+    fn required(&self, label: &'static str) -> Result<&[u64], RaceParseError> {
+        self.row(label).ok_or(RaceParseError::MissingRow(label))
+    }
 
+    fn games(&self) -> Vec<Game> {
+        let times = self.row("Time").unwrap_or_default();
+        let distances = self.row("Distance").unwrap_or_default();
+        times
+            .iter()
+            .zip(distances.iter())
+            .map(|(t, d)| Game::new(*t as u128, *d as u128))
+            .collect_vec()
+    }
+}
+
+fn parse_games(input: &str, reading: Reading) -> Result<Vec<Game>, RaceParseError> {
+    Ok(RaceSheet::new(input, reading)?.games())
+}
+
+fn solve(input: &str, reading: Reading) -> Result<u128> {
+    let games = parse_games(input, reading)?;
     Ok(games.iter().map(|g| g.possibilities()).product())
 }
 
-fn solve_part2() -> Result<usize> {
-    let g = Game::new(38677673, 234102711571236);
-    Ok(g.possibilities())
+fn solve_part1() -> Result<u128> {
+    solve(include_str!("input.txt"), Reading::Races)
+}
+
+fn solve_part2() -> Result<u128> {
+    solve(include_str!("input.txt"), Reading::Kerned)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use num::BigUint;
 
     #[test]
     fn day6_part1() -> Result<()> {
@@ -91,4 +248,96 @@ mod test {
         assert_ne!(s, 0);
         Ok(())
     }
+
+    #[test]
+    fn day6_readings() -> Result<()> {
+        let input = "Time:      7  15   30\nDistance:  9  40  200\n";
+        assert_eq!(solve(input, Reading::Races)?, 288);
+        assert_eq!(solve(input, Reading::Kerned)?, 71503);
+        let games = parse_games(input, Reading::Kerned)?;
+        assert_eq!((games[0].time, games[0].distance), (71530, 940200));
+        Ok(())
+    }
+
+    #[test]
+    fn day6_sheet_errors() -> Result<()> {
+        let loose = "  Time:\t7  15 30  \n\nDistance: 9 40   200\nHeat: 1 2 3";
+        let sheet = RaceSheet::new(loose, Reading::Races)?;
+        assert_eq!(sheet.row("Heat"), Some([1, 2, 3].as_slice()));
+        assert_eq!(sheet.games().len(), 3);
+
+        let short = "Time: 7 15 30\nDistance: 9 40\n";
+        assert_eq!(
+            parse_games(short, Reading::Races).unwrap_err(),
+            RaceParseError::RowLength {
+                label: "Distance".to_string(),
+                expected: 3,
+                found: 2
+            }
+        );
+        assert_eq!(parse_games(short, Reading::Kerned)?.len(), 1);
+        assert_eq!(
+            parse_games("Time: 7\n", Reading::Races).unwrap_err(),
+            RaceParseError::MissingRow("Distance")
+        );
+        assert_eq!(
+            parse_games("Time: 7\nDistance 9", Reading::Races).unwrap_err(),
+            RaceParseError::Syntax { line: 2 }
+        );
+        assert_eq!(
+            parse_games("Time: 7\nTime: 8\nDistance: 9", Reading::Races).unwrap_err(),
+            RaceParseError::DuplicateRow("Time".to_string())
+        );
+        assert!(matches!(
+            parse_games(
+                "Time: 7\nDistance: 999999999999999999999999999999999999999",
+                Reading::Races
+            ),
+            Err(RaceParseError::Number { .. })
+        ));
+
+        let oversized = "Time: 20000000000000000000\nDistance: 1\n";
+        assert!(matches!(
+            solve(oversized, Reading::Races),
+            Err(e) if matches!(e.downcast_ref(), Some(RaceParseError::Number { .. }))
+        ));
+        let kerned = "Time: 9999999999 9999999999\nDistance: 1 1\n";
+        assert!(solve(kerned, Reading::Kerned).is_err());
+        let largest = format!("Time: {}\nDistance: 1\n", u64::MAX);
+        assert_eq!(solve(&largest, Reading::Races)?, u64::MAX as u128 - 1);
+        Ok(())
+    }
+
+    fn brute_force(time: u64, distance: u64) -> usize {
+        (0..=time).filter(|p| (time - p) * p > distance).count()
+    }
+
+    #[test]
+    fn day6_closed_form() {
+        assert_eq!(Game::new(7_u128, 9).winning_interval(), Some(2..=5));
+        assert_eq!(Game::new(30_u128, 200).winning_interval(), Some(11..=19));
+        assert_eq!(Game::new(71530_u128, 940200).possibilities(), 71503);
+        assert_eq!(Game::new(4_u128, 4).winning_interval(), None);
+        assert_eq!(Game::new(0_u128, 0).possibilities(), 0);
+        for time in 0..60 {
+            for distance in 0..=(time * time / 4 + 2) {
+                let g = Game::new(time as u128, distance as u128);
+                assert_eq!(
+                    g.possibilities() as usize,
+                    brute_force(time, distance),
+                    "{} {}",
+                    time,
+                    distance
+                );
+            }
+        }
+
+        let time = BigUint::from(u128::MAX) * 3_u32;
+        let distance = BigUint::from(u128::MAX);
+        let g = Game::new(time.clone(), distance.clone());
+        let r = g.winning_interval().unwrap();
+        assert!(g.beats_record(r.start()));
+        assert!(!g.beats_record(&(r.start() - 1_u32)));
+        assert_eq!(r.end(), &(time - r.start()));
+    }
 }
@@ -1,182 +1,357 @@
-use crate::day7::HandType::{
-    FiveOfAKind, FourOfAKind, FullHouse, HighCard, OnePair, ThreeOfAKind, TwoPair,
-};
-use anyhow::Result;
-use itertools::Itertools;
-use nom::InputIter;
-use once_cell::sync::Lazy;
+use anyhow::{anyhow, bail, Result};
+use itertools::{Itertools, MinMaxResult};
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 
-static CARD_RANKS: Lazy<HashMap<char, usize>> = Lazy::new(|| {
-    vec![
-        ('A', 14),
-        ('K', 13),
-        ('Q', 12),
-        ('J', 11),
-        ('T', 10),
-        ('9', 9),
-        ('8', 8),
-        ('7', 7),
-        ('6', 6),
-        ('5', 5),
-        ('4', 4),
-        ('3', 3),
-        ('2', 2),
-        ('J', 1),
-    ]
-    .into_iter()
-    .collect()
-});
-
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
-enum HandType {
-    FiveOfAKind,
-    FourOfAKind,
-    FullHouse,
-    ThreeOfAKind,
-    TwoPair,
-    OnePair,
-    #[default]
-    HighCard,
-}
-
-impl Into<usize> for &HandType {
-    fn into(self) -> usize {
-        match self {
-            FiveOfAKind => 6,
-            FourOfAKind => 5,
-            FullHouse => 4,
-            ThreeOfAKind => 3,
-            TwoPair => 2,
-            OnePair => 1,
-            HighCard => 0,
-        }
-    }
-}
-
-impl PartialOrd for HandType {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+/// A hand category: the conditions a hand has to meet to belong to it. Group
+/// sizes are those of equal ranks sorted from largest to smallest, e.g.
+/// `[3, 2]` for a full house.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Category {
+    name: &'static str,
+    counts: Option<Vec<usize>>,
+    /// Every rank follows the previous one in card order.
+    straight: bool,
+    /// Every card has the same suit.
+    flush: bool,
 }
-impl Ord for HandType {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let i: usize = self.into();
-        i.cmp(&other.into())
+
+impl Category {
+    fn new(name: &'static str, counts: &[usize]) -> Self {
+        Self {
+            name,
+            counts: Some(counts.to_vec()),
+            straight: false,
+            flush: false,
+        }
+    }
+
+    fn straight(name: &'static str) -> Self {
+        Self {
+            name,
+            counts: None,
+            straight: true,
+            flush: false,
+        }
+    }
+
+    fn flush(name: &'static str) -> Self {
+        Self {
+            name,
+            counts: None,
+            straight: false,
+            flush: true,
+        }
+    }
+
+    fn straight_flush(name: &'static str) -> Self {
+        Self {
+            straight: true,
+            ..Self::flush(name)
+        }
+    }
+
+    /// How many wildcards go to each group of `counts`, largest first, for
+    /// cards whose groups of equal ranks have sizes `held`, also largest
+    /// first: the held groups go to the largest groups in order and the
+    /// groups left over are wildcards only. `None` if they don't fit or the
+    /// category doesn't count groups.
+    fn fill(&self, held: &[usize], wildcards: usize) -> Option<Vec<usize>> {
+        let counts = self.counts.as_ref()?.iter().sorted().rev().collect_vec();
+        if held.len() > counts.len()
+            || held.iter().sum::<usize>() + wildcards != counts.iter().copied().sum::<usize>()
+        {
+            return None;
+        }
+        counts
+            .iter()
+            .enumerate()
+            .map(|(i, n)| n.checked_sub(held.get(i).copied().unwrap_or(0)))
+            .collect()
     }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
-struct Hand {
-    cards: Vec<usize>,
-    bid: u64,
-    rank: HandType,
+/// Everything that varies between ways of ranking hands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RuleSet {
+    /// Number of cards in a hand.
+    hand_size: usize,
+    /// Card ranks from weakest to strongest.
+    card_order: Vec<char>,
+    /// Suits, written after each rank, e.g. `Ah`. Empty for unsuited cards.
+    suits: Vec<char>,
+    /// Ranks standing in for whichever card makes the best hand.
+    wildcards: Vec<char>,
+    /// Categories from weakest to strongest.
+    categories: Vec<Category>,
 }
 
-impl Hand {
-    fn new(input: &str, part2: bool) -> Result<Self> {
-        let parts: Vec<&str> = input.split_whitespace().collect();
-        let mut h = Self {
-            bid: parts[1].parse()?,
-            ..Self::default()
-        };
-        h.cards = parts[0]
-            .iter_elements()
-            .flat_map(|c| CARD_RANKS.get(&c))
-            .cloned()
-            .collect_vec();
+impl RuleSet {
+    fn poker_categories() -> Vec<Category> {
+        vec![
+            Category::new("high card", &[1, 1, 1, 1, 1]),
+            Category::new("one pair", &[2, 1, 1, 1]),
+            Category::new("two pair", &[2, 2, 1]),
+            Category::new("three of a kind", &[3, 1, 1]),
+            Category::new("full house", &[3, 2]),
+            Category::new("four of a kind", &[4, 1]),
+            Category::new("five of a kind", &[5]),
+        ]
+    }
 
-        if part2 {
-            h.rank_part2();
-        } else {
-            h.rank_part1();
+    /// Camel Cards as in part 1.
+    fn part1() -> Self {
+        Self {
+            hand_size: 5,
+            card_order: "23456789TJQKA".chars().collect_vec(),
+            suits: vec![],
+            wildcards: vec![],
+            categories: Self::poker_categories(),
         }
+    }
 
-        Ok(h)
+    /// Part 2: `J` is a joker, and the weakest card when breaking ties.
+    fn part2() -> Self {
+        Self {
+            card_order: "J23456789TQKA".chars().collect_vec(),
+            wildcards: vec!['J'],
+            ..Self::part1()
+        }
     }
 
-    fn rank_part1(&mut self) {
-        let mut counts = HashMap::new();
-        for i in self.cards.iter() {
-            counts.entry(*i).and_modify(|e| *e += 1).or_insert(1);
+    /// Five card poker with suits, aces high only. Ties are still broken card
+    /// by card as in Camel Cards.
+    fn poker() -> Self {
+        Self {
+            suits: "cdhs".chars().collect_vec(),
+            categories: vec![
+                Category::new("high card", &[1, 1, 1, 1, 1]),
+                Category::new("one pair", &[2, 1, 1, 1]),
+                Category::new("two pair", &[2, 2, 1]),
+                Category::new("three of a kind", &[3, 1, 1]),
+                Category::straight("straight"),
+                Category::flush("flush"),
+                Category::new("full house", &[3, 2]),
+                Category::new("four of a kind", &[4, 1]),
+                Category::straight_flush("straight flush"),
+            ],
+            ..Self::part1()
         }
-        self.rank = match counts.values().max().unwrap() {
-            5 => FiveOfAKind,
-            4 => FourOfAKind,
-            3 => {
-                if counts.values().any(|c| *c == 2) {
-                    FullHouse
-                } else {
-                    ThreeOfAKind
-                }
+    }
+
+    fn card_value(&self, card: char) -> Option<usize> {
+        self.card_order.iter().position(|c| *c == card)
+    }
+
+    /// Splits `labels` into ranks and suits, checking both against the rule
+    /// set and the number of cards against the hand size.
+    fn parse_cards(&self, labels: &str) -> Result<(Vec<char>, Vec<char>)> {
+        let chars = labels.chars().collect_vec();
+        let (ranks, suits) = if self.suits.is_empty() {
+            (chars, vec![])
+        } else {
+            if !chars.len().is_multiple_of(2) {
+                bail!("expected a rank and a suit for every card in {:?}", labels);
             }
-            2 => {
-                if counts.values().filter(|c| (*c).eq(&2)).count() == 2 {
-                    TwoPair
-                } else {
-                    OnePair
-                }
+            let (ranks, suits): (Vec<_>, Vec<_>) = chars.iter().tuples().unzip();
+            if let Some(s) = suits.iter().find(|s| !self.suits.contains(s)) {
+                bail!("unknown suit {:?}", s);
             }
-            1 => HighCard,
-            _ => unreachable!("can't have this count of cards"),
+            (ranks, suits)
+        };
+        if let Some(c) = ranks.iter().find(|c| self.card_value(**c).is_none()) {
+            bail!("unknown card {:?}", c);
+        }
+        if ranks.len() != self.hand_size {
+            bail!(
+                "expected {} cards, found {} in {:?}",
+                self.hand_size,
+                ranks.len(),
+                labels
+            );
         }
+        Ok((ranks, suits))
     }
 
-    fn rank_part2(&mut self) {
-        let mut counts = HashMap::new();
-        for i in self.cards.iter() {
-            counts.entry(*i).and_modify(|e| *e += 1).or_insert(1);
-        }
-        let joker_count = counts.get(&1).cloned().unwrap_or_default();
-        self.rank = match counts.values().max().unwrap() {
-            5 => FiveOfAKind,
-            4 => {
-                if joker_count > 0 {
-                    FiveOfAKind
-                } else {
-                    FourOfAKind
-                }
-            }
-            3 => {
-                if counts.values().any(|c| *c == 2) {
-                    match joker_count {
-                        3 | 2 => FiveOfAKind,
-                        1 => FourOfAKind,
-                        _ => FullHouse,
-                    }
-                } else {
-                    match joker_count {
-                        1 | 3 => FourOfAKind,
-                        _ => ThreeOfAKind,
-                    }
-                }
-            }
-            2 => {
-                if counts.values().filter(|c| (*c).eq(&2)).count() == 2 {
-                    match joker_count {
-                        2 => FourOfAKind,
-                        1 => FullHouse,
-                        _ => TwoPair,
-                    }
-                } else if joker_count > 0 {
-                    ThreeOfAKind
-                } else {
-                    OnePair
-                }
-            }
-            1 => {
-                if joker_count > 0 {
-                    OnePair
-                } else {
-                    HighCard
-                }
+    /// Index in `categories` of the best category unsuited cards can make.
+    fn classify(&self, cards: &[char]) -> Option<usize> {
+        self.classify_suited(cards, &[])
+    }
+
+    /// Index in `categories` of the best category the cards can make, `suits`
+    /// being empty for unsuited cards. Each category is checked on its own,
+    /// so the wildcards can make whichever groups it asks for, or fill in
+    /// any missing rank or suit of a straight or flush.
+    fn classify_suited(&self, ranks: &[char], suits: &[char]) -> Option<usize> {
+        let mut counts: HashMap<char, usize> = HashMap::new();
+        let mut wildcards = 0;
+        for c in ranks {
+            if self.wildcards.contains(c) {
+                wildcards += 1;
+            } else {
+                *counts.entry(*c).or_default() += 1;
             }
-            _ => unreachable!("can't have this count of cards"),
         }
+        let values = counts
+            .keys()
+            .flat_map(|c| self.card_value(*c))
+            .collect_vec();
+        let straight = counts.values().all(|n| *n == 1)
+            && ranks.len() <= self.card_order.len()
+            && match values.iter().minmax() {
+                MinMaxResult::MinMax(low, high) => high - low < ranks.len(),
+                _ => true,
+            };
+        let flush = !suits.is_empty()
+            && ranks
+                .iter()
+                .zip(suits)
+                .filter(|(c, _)| !self.wildcards.contains(c))
+                .map(|(_, s)| s)
+                .all_equal();
+
+        let held = counts.into_values().sorted().rev().collect_vec();
+        self.categories.iter().rposition(|c| {
+            (c.counts.is_none() || c.fill(&held, wildcards).is_some())
+                && (!c.straight || straight)
+                && (!c.flush || flush)
+        })
+    }
+
+    /// The distinct cards the wildcards of a hand stand for once it is put
+    /// in `category`: the missing ranks of a straight, the strongest ranks
+    /// not held for a flush, the groups of equal cards they complete, the
+    /// strongest held first, otherwise, followed by the flush suit for
+    /// flushes. Empty without wildcards.
+    fn wildcard_substitutes(&self, ranks: &[char], suits: &[char], category: usize) -> Vec<String> {
+        let wildcards = ranks.iter().filter(|c| self.wildcards.contains(c)).count();
+        if wildcards == 0 {
+            return vec![];
+        }
+        let held = ranks
+            .iter()
+            .filter(|c| !self.wildcards.contains(c))
+            .cloned()
+            .collect_vec();
+        let category = &self.categories[category];
+        let substitutes = if category.straight {
+            let values = held.iter().flat_map(|c| self.card_value(*c)).collect_vec();
+            let top = self.card_order.len().saturating_sub(ranks.len());
+            let start = values.iter().min().map_or(top, |low| (*low).min(top));
+            (start..start + ranks.len())
+                .filter(|v| !values.contains(v))
+                .map(|v| self.card_order[v])
+                .collect_vec()
+        } else if category.flush && category.counts.is_none() {
+            self.card_order
+                .iter()
+                .rev()
+                .filter(|c| !self.wildcards.contains(c) && !held.contains(c))
+                .take(wildcards)
+                .cloned()
+                .collect_vec()
+        } else if let Some(fill) = category.fill(
+            &held
+                .iter()
+                .counts()
+                .into_values()
+                .sorted()
+                .rev()
+                .collect_vec(),
+            wildcards,
+        ) {
+            let groups = held
+                .iter()
+                .counts()
+                .into_iter()
+                .sorted_by_key(|(c, n)| (*n, self.card_value(**c)))
+                .rev()
+                .map(|(c, _)| *c)
+                .collect_vec();
+            let mut absent = self
+                .card_order
+                .iter()
+                .rev()
+                .filter(|c| !self.wildcards.contains(c) && !held.contains(c));
+            fill.iter()
+                .enumerate()
+                .filter(|(_, n)| **n > 0)
+                .flat_map(|(i, _)| groups.get(i).or_else(|| absent.next()).cloned())
+                .collect_vec()
+        } else {
+            held.iter()
+                .counts()
+                .into_iter()
+                .max_by_key(|(c, n)| (*n, self.card_value(**c)))
+                .map(|(c, _)| *c)
+                .or_else(|| self.card_order.last().cloned())
+                .into_iter()
+                .collect_vec()
+        };
+        let suit = if category.flush {
+            ranks
+                .iter()
+                .zip(suits)
+                .find(|(c, _)| !self.wildcards.contains(c))
+                .map(|(_, s)| *s)
+                .or_else(|| self.suits.last().cloned())
+        } else {
+            None
+        };
+        substitutes
+            .into_iter()
+            .map(|c| c.to_string() + &suit.map(String::from).unwrap_or_default())
+            .collect_vec()
+    }
+}
+
+/// A hand and its bid. Hands compare by category then card by card, so two
+/// hands with the same cards are equal whatever their bids.
+#[derive(Clone, Debug, Default)]
+struct Hand {
+    labels: String,
+    cards: Vec<usize>,
+    /// Suit of each card, empty for unsuited cards.
+    suits: Vec<char>,
+    bid: u64,
+    /// Index of the hand's category in its rule set.
+    rank: usize,
+}
+
+impl Hand {
+    fn new(input: &str, rules: &RuleSet) -> Result<Self> {
+        let (labels, bid) = input
+            .split_whitespace()
+            .collect_tuple()
+            .ok_or_else(|| anyhow!("expected cards and a bid in {:?}", input))?;
+        let (ranks, suits) = rules.parse_cards(labels)?;
+        let cards = ranks
+            .iter()
+            .flat_map(|c| rules.card_value(*c))
+            .collect_vec();
+        let rank = rules
+            .classify_suited(&ranks, &suits)
+            .ok_or_else(|| anyhow!("{} matches no category", input))?;
+
+        Ok(Self {
+            labels: labels.to_string(),
+            cards,
+            suits,
+            bid: bid.parse()?,
+            rank,
+        })
+    }
+}
+
+impl PartialEq for Hand {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
+impl Eq for Hand {}
+
 impl PartialOrd for Hand {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -201,24 +376,125 @@ impl Ord for Hand {
     }
 }
 
-fn solve(part2: bool) -> Result<u64> {
-    let data = include_str!("input.txt");
+/// Why one hand ranks where it does against another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Comparison {
+    ordering: Ordering,
+    categories: [&'static str; 2],
+    /// The cards each hand's wildcards were counted as.
+    substitutes: [Vec<String>; 2],
+    /// Position of the card that broke the tie, for hands of one category.
+    first_difference: Option<usize>,
+}
+
+impl Comparison {
+    fn new(a: &Hand, b: &Hand, rules: &RuleSet) -> Self {
+        let first_difference = if a.rank == b.rank {
+            a.cards.iter().zip(b.cards.iter()).position(|(x, y)| x != y)
+        } else {
+            None
+        };
+        let substitute = |h: &Hand| {
+            let ranks = h.cards.iter().map(|v| rules.card_order[*v]).collect_vec();
+            rules.wildcard_substitutes(&ranks, &h.suits, h.rank)
+        };
+        Self {
+            ordering: a.cmp(b),
+            categories: [rules.categories[a.rank].name, rules.categories[b.rank].name],
+            substitutes: [substitute(a), substitute(b)],
+            first_difference,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, (category, substitute)) in self
+            .categories
+            .iter()
+            .zip(self.substitutes.iter())
+            .enumerate()
+        {
+            write!(f, "hand {}: {}", i + 1, category)?;
+            if !substitute.is_empty() {
+                write!(f, " (wildcards as {})", substitute.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        let verdict = match self.ordering {
+            Ordering::Less => "hand 1 is weaker",
+            Ordering::Equal => "hands are equal",
+            Ordering::Greater => "hand 1 is stronger",
+        };
+        match self.first_difference {
+            Some(i) => writeln!(f, "{}, decided by card {}", verdict, i + 1),
+            None if self.categories[0] == self.categories[1] => writeln!(f, "{}", verdict),
+            None => writeln!(f, "{}, decided by category", verdict),
+        }
+    }
+}
+
+/// One line of the final standings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RankedHand {
+    rank: usize,
+    labels: String,
+    category: &'static str,
+    bid: u64,
+    winnings: u64,
+}
+
+/// Every hand of `data` from weakest to strongest, with what it contributes
+/// to the total winnings. Blank lines are skipped, any other line that isn't
+/// a hand is an error.
+fn ranking(data: &str, rules: &RuleSet) -> Result<Vec<RankedHand>> {
     let hands = data
         .lines()
-        .flat_map(|l| Hand::new(l, part2))
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| Hand::new(l, rules).map_err(|e| anyhow!("line {}: {}", n + 1, e)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(hands
+        .into_iter()
         .sorted()
-        .collect_vec();
-    let score = hands
-        .iter()
         .enumerate()
-        .map(|(i, h)| h.bid * (i + 1) as u64)
-        .sum();
-    Ok(score)
+        .map(|(i, h)| RankedHand {
+            rank: i + 1,
+            category: rules.categories[h.rank].name,
+            winnings: h.bid * (i + 1) as u64,
+            bid: h.bid,
+            labels: h.labels,
+        })
+        .collect_vec())
+}
+
+impl Display for RankedHand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>5} {} {:<15} {:>5} {:>8}",
+            self.rank, self.labels, self.category, self.bid, self.winnings
+        )
+    }
+}
+
+fn total_winnings(data: &str, rules: &RuleSet) -> Result<u64> {
+    Ok(ranking(data, rules)?.iter().map(|h| h.winnings).sum())
+}
+
+fn solve(part2: bool) -> Result<u64> {
+    let rules = if part2 {
+        RuleSet::part2()
+    } else {
+        RuleSet::part1()
+    };
+    total_winnings(include_str!("input.txt"), &rules)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::rng::Rng;
 
     #[test]
     fn day7_part1() -> Result<()> {
@@ -233,4 +509,288 @@ mod test {
         println!("{}", s);
         Ok(())
     }
+
+    #[test]
+    fn day7_rule_sets() -> Result<()> {
+        let input = "32T3K 765\nT55J5 684\nKK677 28\nKTJJT 220\nQQQJA 483";
+        assert_eq!(total_winnings(input, &RuleSet::part1())?, 6440);
+        assert_eq!(total_winnings(input, &RuleSet::part2())?, 5905);
+        let spaced = format!("\n{}\n\n", input.replace('\n', "\n  \n"));
+        assert_eq!(total_winnings(&spaced, &RuleSet::part1())?, 6440);
+        let err = total_winnings(&format!("{}\n32T3K", input), &RuleSet::part1()).unwrap_err();
+        assert!(err.to_string().starts_with("line 6: "), "{}", err);
+        assert!(total_winnings("32T3K 765\n32X3K 1", &RuleSet::part1()).is_err());
+
+        let name = |rules: &RuleSet, cards: &str| {
+            let cards = cards.chars().collect_vec();
+            rules.classify(&cards).map(|i| rules.categories[i].name)
+        };
+        let part2 = RuleSet::part2();
+        assert_eq!(name(&part2, "JJJJJ"), Some("five of a kind"));
+        assert_eq!(name(&part2, "KTJJT"), Some("four of a kind"));
+        assert_eq!(name(&part2, "2345J"), Some("one pair"));
+        assert_eq!(name(&RuleSet::part1(), "KTJJT"), Some("two pair"));
+
+        let jack = Hand::new("J2222 1", &RuleSet::part1())?;
+        let two = Hand::new("2J222 1", &RuleSet::part1())?;
+        assert!(jack > two);
+        let joker = Hand::new("J2222 1", &part2)?;
+        let two = Hand::new("2J222 1", &part2)?;
+        assert!(joker < two);
+        assert!(Hand::new("X2222 1", &part2).is_err());
+        assert_eq!(Hand::new("22222 1", &part2)?, Hand::new("22222 2", &part2)?);
+        Ok(())
+    }
+
+    #[test]
+    fn day7_generic_hands() -> Result<()> {
+        let poker = RuleSet::poker();
+        let name = |rules: &RuleSet, hand: &str| -> Result<&str> {
+            Ok(rules.categories[Hand::new(hand, rules)?.rank].name)
+        };
+        assert_eq!(name(&poker, "2h3h4h5h6h 1")?, "straight flush");
+        assert_eq!(name(&poker, "2c3d4h5s6c 1")?, "straight");
+        assert_eq!(name(&poker, "2h9h4h5hKh 1")?, "flush");
+        assert_eq!(name(&poker, "AhAdAcKsKh 1")?, "full house");
+        assert_eq!(name(&poker, "2c3d4h5s7c 1")?, "high card");
+        assert!(Hand::new("2h3h4h5h6h 1", &poker)? > Hand::new("AhAdAcAsKh 1", &poker)?);
+        assert!(Hand::new("2h3h4h5h 1", &poker).is_err());
+        assert!(Hand::new("2x3h4h5h6h 1", &poker).is_err());
+        assert!(Hand::new("2h3h4h5h6 1", &poker).is_err());
+
+        let jokers = RuleSet {
+            wildcards: vec!['J'],
+            ..RuleSet::poker()
+        };
+        assert_eq!(name(&jokers, "2h3dJc5s6h 1")?, "straight");
+        assert_eq!(name(&jokers, "2h3hJc5h6h 1")?, "straight flush");
+        assert_eq!(name(&jokers, "2h3hJc5h7h 1")?, "flush");
+        let straight = Hand::new("2h3dJc5s6h 1", &jokers)?;
+        let flush = Hand::new("2h3hJc5h7h 1", &jokers)?;
+        let c = Comparison::new(&straight, &flush, &jokers);
+        assert_eq!(c.substitutes, [vec!["4"], vec!["Ah"]]);
+        assert_eq!(
+            c.to_string(),
+            "hand 1: straight (wildcards as 4)\nhand 2: flush (wildcards as Ah)\nhand 1 is weaker, decided by category\n"
+        );
+        let substitutes = |hand: &str| -> Result<Vec<String>> {
+            let h = Hand::new(hand, &jokers)?;
+            Ok(Comparison::new(&h, &h, &jokers).substitutes[0].clone())
+        };
+        assert_eq!(substitutes("2h3hJc5h6h 1")?, vec!["4h"]);
+        assert_eq!(substitutes("JhJd5c6s7h 1")?, vec!["8", "9"]);
+        assert_eq!(substitutes("JhJd5c5s7h 1")?, vec!["5"]);
+        assert!(substitutes("2h3d4c5s6h 1")?.is_empty());
+
+        let triples = RuleSet {
+            hand_size: 3,
+            card_order: "abc".chars().collect_vec(),
+            suits: vec![],
+            wildcards: vec![],
+            categories: vec![
+                Category::new("all different", &[1, 1, 1]),
+                Category::new("pair", &[2, 1]),
+                Category::new("triple", &[3]),
+            ],
+        };
+        assert!(all_hands(&triples).all(|h| triples.classify(&h).is_some()));
+        assert_eq!(total_winnings("abc 1\naab 2\nccc 3\nbba 4", &triples)?, 29);
+        assert!(Hand::new("abca 1", &triples).is_err());
+
+        // two pair beats three of a kind, so a joker pairs up the single card
+        let pairs = RuleSet {
+            hand_size: 4,
+            card_order: "Jabc".chars().collect_vec(),
+            suits: vec![],
+            wildcards: vec!['J'],
+            categories: vec![
+                Category::new("all different", &[1, 1, 1, 1]),
+                Category::new("pair", &[2, 1, 1]),
+                Category::new("three of a kind", &[3, 1]),
+                Category::new("two pair", &[2, 2]),
+                Category::new("four of a kind", &[4]),
+            ],
+        };
+        assert!(all_hands(&pairs).all(|h| pairs.classify(&h).is_some()));
+        assert_eq!(name(&pairs, "aabJ 1")?, "two pair");
+        assert_eq!(name(&pairs, "abJJ 1")?, "two pair");
+        assert_eq!(name(&pairs, "aaJJ 1")?, "four of a kind");
+        let h = Hand::new("aabJ 1", &pairs)?;
+        assert_eq!(Comparison::new(&h, &h, &pairs).substitutes[0], vec!["b"]);
+        let h = Hand::new("abJJ 1", &pairs)?;
+        assert_eq!(
+            Comparison::new(&h, &h, &pairs).substitutes[0],
+            vec!["b", "a"]
+        );
+        let no_triples = RuleSet {
+            categories: pairs
+                .categories
+                .iter()
+                .filter(|c| c.name != "three of a kind")
+                .cloned()
+                .collect_vec(),
+            ..pairs.clone()
+        };
+        assert_eq!(name(&no_triples, "aabJ 1")?, "two pair");
+        assert_eq!(name(&no_triples, "aJJJ 1")?, "four of a kind");
+        assert!(Hand::new("aaab 1", &no_triples).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn day7_explain() -> Result<()> {
+        let rules = RuleSet::part2();
+        let a = Hand::new("KTJJT 220", &rules)?;
+        let b = Hand::new("QQQJA 483", &rules)?;
+        let c = Comparison::new(&a, &b, &rules);
+        assert_eq!(c.ordering, Ordering::Greater);
+        assert_eq!(c.categories, ["four of a kind", "four of a kind"]);
+        assert_eq!(c.substitutes, [vec!["T"], vec!["Q"]]);
+        assert_eq!(c.first_difference, Some(0));
+        assert_eq!(
+            c.to_string(),
+            "hand 1: four of a kind (wildcards as T)\nhand 2: four of a kind (wildcards as Q)\nhand 1 is stronger, decided by card 1\n"
+        );
+
+        let d = Hand::new("32T3K 765", &rules)?;
+        let c = Comparison::new(&d, &a, &rules);
+        assert_eq!((c.ordering, c.first_difference), (Ordering::Less, None));
+        assert!(c.to_string().ends_with("decided by category\n"));
+        assert_eq!(rules.wildcard_substitutes(&['J'; 5], &[], 6), vec!["A"]);
+
+        let input = "32T3K 765\nT55J5 684\nKK677 28\nKTJJT 220\nQQQJA 483";
+        let standings = ranking(input, &rules)?;
+        assert_eq!(
+            standings.iter().map(|h| h.labels.as_str()).collect_vec(),
+            vec!["32T3K", "KK677", "T55J5", "QQQJA", "KTJJT"]
+        );
+        assert_eq!(standings[4].winnings, 1100);
+        assert_eq!(
+            standings[0].to_string(),
+            "    1 32T3K one pair          765      765"
+        );
+        Ok(())
+    }
+
+    /// Every hand of `rules.hand_size` cards from the labels of `rules`.
+    fn all_hands(rules: &RuleSet) -> impl Iterator<Item = Vec<char>> + '_ {
+        (0..rules.hand_size)
+            .map(|_| rules.card_order.iter().cloned())
+            .multi_cartesian_product()
+    }
+
+    /// Category straight from the group sizes, independently of `classify`.
+    fn oracle_category(cards: &[char]) -> usize {
+        let counts = cards
+            .iter()
+            .counts()
+            .into_values()
+            .sorted()
+            .rev()
+            .collect_vec();
+        match counts.as_slice() {
+            [5] => 6,
+            [4, 1] => 5,
+            [3, 2] => 4,
+            [3, 1, 1] => 3,
+            [2, 2, 1] => 2,
+            [2, 1, 1, 1] => 1,
+            _ => 0,
+        }
+    }
+
+    /// Best category over every way of replacing the jokers. Replacements are
+    /// drawn from the cards already in the hand plus one absent card, since
+    /// all absent cards are interchangeable for these categories.
+    fn oracle_with_jokers(cards: &[char], rules: &RuleSet) -> usize {
+        let jokers = cards
+            .iter()
+            .positions(|c| rules.wildcards.contains(c))
+            .collect_vec();
+        let mut candidates = cards
+            .iter()
+            .filter(|c| !rules.wildcards.contains(c))
+            .cloned()
+            .unique()
+            .collect_vec();
+        candidates.extend(
+            rules
+                .card_order
+                .iter()
+                .find(|c| !rules.wildcards.contains(c) && !cards.contains(c)),
+        );
+        (0..jokers.len())
+            .map(|_| candidates.iter())
+            .multi_cartesian_product()
+            .map(|replacement| {
+                let mut hand = cards.to_vec();
+                for (i, c) in jokers.iter().zip(replacement) {
+                    hand[*i] = *c;
+                }
+                oracle_category(&hand)
+            })
+            .max()
+            .unwrap_or_else(|| oracle_category(cards))
+    }
+
+    #[test]
+    fn day7_exhaustive_classification() {
+        let part1 = RuleSet::part1();
+        for cards in all_hands(&part1) {
+            assert_eq!(
+                part1.classify(&cards),
+                Some(oracle_category(&cards)),
+                "{:?}",
+                cards
+            );
+        }
+        let part2 = RuleSet::part2();
+        for cards in all_hands(&part2) {
+            assert_eq!(
+                part2.classify(&cards),
+                Some(oracle_with_jokers(&cards, &part2)),
+                "{:?}",
+                cards
+            );
+        }
+    }
+
+    #[test]
+    fn day7_hand_total_order() -> Result<()> {
+        let mut rng = Rng::new(47);
+        for rules in [RuleSet::part1(), RuleSet::part2()] {
+            let mut random_hand = || {
+                // few distinct labels so equal categories and ties are common
+                let labels = (0..5)
+                    .map(|_| rules.card_order[rng.below(4) * 3])
+                    .collect::<String>();
+                Hand::new(&format!("{} {}", labels, rng.below(1000)), &rules)
+            };
+            let hands = (0..300)
+                .map(|_| random_hand())
+                .collect::<Result<Vec<_>>>()?;
+
+            for a in hands.iter() {
+                assert_eq!(a.cmp(a), Ordering::Equal);
+                for b in hands.iter() {
+                    let ab = a.cmp(b);
+                    assert_eq!(ab, b.cmp(a).reverse());
+                    assert_eq!(a.partial_cmp(b), Some(ab));
+                    assert_eq!(ab == Ordering::Equal, a.cards == b.cards);
+                    assert_eq!(a == b, ab == Ordering::Equal);
+                }
+            }
+            for (a, b, c) in hands.iter().take(60).tuple_combinations() {
+                if a <= b && b <= c {
+                    assert!(a <= c);
+                }
+                if a >= b && b >= c {
+                    assert!(a >= c);
+                }
+            }
+            let sorted = hands.iter().sorted().collect_vec();
+            assert!(sorted.iter().tuple_windows().all(|(x, y)| x <= y));
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,223 @@
+use super::Network;
+use itertools::Itertools;
+use num::Integer;
+
+/// Where one ghost stands on end nodes. The walk is deterministic in its
+/// (node, instruction index) state, so after `cycle_start` steps it loops
+/// every `cycle_len` steps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Ghost {
+    pub(super) cycle_start: u64,
+    pub(super) cycle_len: u64,
+    /// Steps before `cycle_start` after which the ghost is on an end node.
+    pub(super) prefix_hits: Vec<u64>,
+    /// Steps in `cycle_start..cycle_start + cycle_len` after which the ghost
+    /// is on an end node.
+    pub(super) cycle_hits: Vec<u64>,
+}
+
+impl Ghost {
+    /// Walks from `start` until a state repeats, `ends` flagging end nodes by
+    /// id.
+    pub(super) fn new(network: &Network, start: u32, ends: &[bool]) -> Self {
+        let len = network.directions.len();
+        let mut seen = vec![u64::MAX; network.names.len() * len];
+        let mut hits = vec![];
+        let mut node = start;
+        let mut t = 0_u64;
+        loop {
+            let state = node as usize * len + t as usize % len;
+            if seen[state] != u64::MAX {
+                let cycle_start = seen[state];
+                let (prefix_hits, cycle_hits) = hits.into_iter().partition(|h| *h < cycle_start);
+                return Self {
+                    cycle_start,
+                    cycle_len: t - cycle_start,
+                    prefix_hits,
+                    cycle_hits,
+                };
+            }
+            seen[state] = t;
+            if ends[node as usize] {
+                hits.push(t);
+            }
+            node = network.step(node, t as usize);
+            t += 1;
+        }
+    }
+
+    /// Whether the ghost is on an end node after `t` steps.
+    pub(super) fn at_end(&self, t: u64) -> bool {
+        if t < self.cycle_start {
+            self.prefix_hits.contains(&t)
+        } else {
+            let t = self.cycle_start + (t - self.cycle_start) % self.cycle_len;
+            self.cycle_hits.contains(&t)
+        }
+    }
+}
+
+/// The `x` with `x ≡ a (mod m)` and `x ≡ b (mod n)`, as `(x, lcm(m, n))`
+/// with `0 <= x < lcm(m, n)`, if the moduli share no conflicting factor.
+fn crt((a, m): (i128, i128), (b, n): (i128, i128)) -> Option<(i128, i128)> {
+    let e = m.extended_gcd(&n);
+    if (b - a) % e.gcd != 0 {
+        return None;
+    }
+    let step = n / e.gcd;
+    let k = ((b - a) / e.gcd).rem_euclid(step) * e.x.rem_euclid(step) % step;
+    let lcm = m / e.gcd * n;
+    Some(((a + m * k).rem_euclid(lcm), lcm))
+}
+
+/// The usual shortcut, valid when every ghost only ever reaches an end node
+/// at multiples of its cycle length: they first meet at the LCM of those.
+fn lcm_shortcut(ghosts: &[Ghost]) -> Option<u64> {
+    let single_hit = ghosts.iter().all(|g| {
+        g.prefix_hits.is_empty() && g.cycle_hits.len() == 1 && g.cycle_hits[0] % g.cycle_len == 0
+    });
+    if !single_hit {
+        return None;
+    }
+    let t = ghosts.iter().fold(1, |acc, g| acc.lcm(&g.cycle_len));
+    ghosts.iter().all(|g| g.at_end(t)).then_some(t)
+}
+
+/// The fewest steps, at least one, after which every ghost is on an end node
+/// at once.
+pub(super) fn first_meeting(ghosts: &[Ghost]) -> Option<u64> {
+    if let Some(t) = lcm_shortcut(ghosts) {
+        return Some(t);
+    }
+    // Before the last ghost enters its cycle, it has to be on one of its own
+    // prefix hits.
+    let last = ghosts.iter().max_by_key(|g| g.cycle_start)?;
+    if let Some(t) = last
+        .prefix_hits
+        .iter()
+        .find(|t| **t >= 1 && ghosts.iter().all(|g| g.at_end(**t)))
+    {
+        return Some(*t);
+    }
+
+    // Afterwards every ghost is in its cycle, so each one restricts the step
+    // count to a few residues modulo its cycle length.
+    let mut residues = vec![(0_i128, 1_i128)];
+    for g in ghosts {
+        let m = g.cycle_len as i128;
+        residues = residues
+            .iter()
+            .cartesian_product(g.cycle_hits.iter())
+            .filter_map(|(r, h)| crt(*r, (*h as i128 % m, m)))
+            .unique()
+            .collect_vec();
+    }
+    let from = last.cycle_start.max(1) as i128;
+    residues
+        .into_iter()
+        .map(|(r, m)| from + (r - from).rem_euclid(m))
+        .min()
+        .and_then(|t| u64::try_from(t).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rng::Rng;
+    use anyhow::Result;
+
+    fn ghost(cycle_start: u64, cycle_len: u64, prefix_hits: &[u64], cycle_hits: &[u64]) -> Ghost {
+        Ghost {
+            cycle_start,
+            cycle_len,
+            prefix_hits: prefix_hits.to_vec(),
+            cycle_hits: cycle_hits.to_vec(),
+        }
+    }
+
+    #[test]
+    fn day8_cycles() -> Result<()> {
+        let network = Network::new(
+            "LR
+
+11A = (11B, XXX)
+11B = (XXX, 11Z)
+11Z = (11B, XXX)
+22A = (22B, XXX)
+22B = (22C, 22C)
+22C = (22Z, 22Z)
+22Z = (22B, 22B)
+XXX = (XXX, XXX)",
+        )?;
+        let ends = network.flags(|n| n.ends_with('Z'));
+        let ghosts = network
+            .nodes_where(|n| n.ends_with('A'))
+            .into_iter()
+            .map(|s| Ghost::new(&network, s, &ends))
+            .collect_vec();
+        assert_eq!(
+            ghosts,
+            vec![ghost(1, 2, &[], &[2]), ghost(1, 6, &[], &[3, 6])]
+        );
+        assert_eq!(first_meeting(&ghosts), Some(6));
+
+        // first hits at 3 and 5, but not every cycle length apart
+        let offset = [ghost(0, 4, &[], &[3]), ghost(0, 6, &[], &[5])];
+        assert_eq!(lcm_shortcut(&offset), None);
+        assert_eq!(first_meeting(&offset), Some(11));
+        assert_eq!(
+            first_meeting(&[ghost(0, 4, &[], &[1]), ghost(0, 6, &[], &[0])]),
+            None
+        );
+        let prefix = [ghost(5, 3, &[2], &[6]), ghost(0, 2, &[], &[0])];
+        assert_eq!(first_meeting(&prefix), Some(2));
+        let late = [ghost(5, 2, &[], &[6]), ghost(0, 2, &[], &[0])];
+        assert_eq!(lcm_shortcut(&late), None);
+        assert_eq!(first_meeting(&late), Some(6));
+        assert_eq!(
+            first_meeting(&[ghost(0, 3, &[], &[0]), ghost(1, 4, &[], &[4])]),
+            Some(12)
+        );
+
+        assert_eq!(crt((2, 4), (4, 6)), Some((10, 12)));
+        assert_eq!(crt((1, 4), (0, 6)), None);
+        Ok(())
+    }
+
+    /// Compares `first_meeting` with walking every ghost step by step on small
+    /// random networks.
+    #[test]
+    fn day8_cycles_brute_force() -> Result<()> {
+        let mut rng = Rng::new(50);
+        let names = ["AA", "BA", "CC", "DZ", "EZ", "FF", "GZ"];
+        for _ in 0..500 {
+            let directions = (0..1 + rng.below(4))
+                .map(|_| if rng.below(2) == 0 { 'L' } else { 'R' })
+                .collect::<String>();
+            let nodes = names
+                .iter()
+                .map(|n| format!("{} = ({}, {})", n, names[rng.below(7)], names[rng.below(7)]))
+                .join("\n");
+            let network = Network::new(&format!("{}\n\n{}", directions, nodes))?;
+            let ends = network.flags(|n| n.ends_with('Z'));
+            let starts = network.nodes_where(|n| n.ends_with('A'));
+            let ghosts = starts
+                .iter()
+                .map(|s| Ghost::new(&network, *s, &ends))
+                .collect_vec();
+
+            let mut positions = starts.clone();
+            let walked = (0..2_000).find_map(|t| {
+                for p in positions.iter_mut() {
+                    *p = network.step(*p, t);
+                }
+                positions
+                    .iter()
+                    .all(|p| ends[*p as usize])
+                    .then_some(t as u64 + 1)
+            });
+            assert_eq!(first_meeting(&ghosts), walked, "{:?}", ghosts);
+        }
+        Ok(())
+    }
+}
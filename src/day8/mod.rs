@@ -1,82 +1,158 @@
-use anyhow::Result;
-use itertools::FoldWhile::{Continue, Done};
+mod cycles;
+
+use anyhow::{anyhow, bail, Result};
+use cycles::{first_meeting, Ghost};
 use itertools::Itertools;
 use nom::bytes::complete::tag;
-use nom::character::complete::alpha1;
-use nom::{IResult, InputIter};
-use num::Integer;
+use nom::character::complete::alphanumeric1;
+use nom::sequence::{delimited, separated_pair};
+use nom::IResult;
 use std::collections::HashMap;
 
-fn parse(input: &str) -> IResult<&str, (Vec<char>, HashMap<String, HashMap<char, String>>)> {
-    let mut lines = input.lines();
-    let directions = lines
-        .next()
-        .map(|l| l.iter_elements().collect_vec())
-        .unwrap_or_default();
-    lines.next();
-
-    let mut m = HashMap::new();
-    for l in lines {
-        let (n, key) = alpha1(l)?;
-        let (n, _) = tag(" = (")(n)?;
-        let (n, left) = alpha1(n)?;
-        let (n, _) = tag(", ")(n)?;
-        let (_, right) = alpha1(n)?;
-        m.insert(
-            key.to_string(),
-            vec![('L', left.to_string()), ('R', right.to_string())]
-                .into_iter()
-                .collect(),
-        );
-    }
-    Ok((input, (directions, m)))
+/// Marks an edge to a node that was referenced before being defined.
+const UNDEFINED: u32 = u32::MAX;
+
+/// The map of the desert: the left/right instructions and the nodes, their
+/// names interned into dense ids so walking a path never allocates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Network {
+    /// 0 for left and 1 for right, indexing into `edges`.
+    directions: Vec<usize>,
+    names: Vec<String>,
+    ids: HashMap<String, u32>,
+    /// Left and right neighbours of each node, by id.
+    edges: Vec<[u32; 2]>,
+}
+
+/// `AAA = (BBB, CCC)`
+fn parse_node(input: &str) -> IResult<&str, (&str, (&str, &str))> {
+    separated_pair(
+        alphanumeric1,
+        tag(" = "),
+        delimited(
+            tag("("),
+            separated_pair(alphanumeric1, tag(", "), alphanumeric1),
+            tag(")"),
+        ),
+    )(input)
 }
 
-fn path_len(
-    directions: &[char],
-    map: &HashMap<String, HashMap<char, String>>,
-    start: &str,
-    predicate: impl Fn(&str) -> bool,
-) -> u64 {
-    let mut loc = start.to_string();
-    let mut path = vec![start.to_string()];
-    let l = directions
-        .iter()
-        .cycle()
-        .fold_while(1_u64, |acc, dir| {
-            let node = map.get(&loc).cloned().unwrap();
-            loc = node.get(dir).cloned().unwrap();
-            path.push(loc.to_string());
-            if predicate(&loc) {
-                Done(acc)
-            } else {
-                Continue(acc + 1)
+impl Network {
+    fn new(input: &str) -> Result<Self> {
+        let mut lines = input.lines();
+        let directions = lines
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| match c {
+                'L' => Ok(0),
+                'R' => Ok(1),
+                _ => Err(anyhow!("unknown direction {:?}", c)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if directions.is_empty() {
+            bail!("no directions");
+        }
+
+        let mut network = Self {
+            directions,
+            ..Self::default()
+        };
+        for l in lines.filter(|l| !l.is_empty()) {
+            let (_, (name, (left, right))) = parse_node(l).map_err(|e| e.to_owned())?;
+            let node = network.intern(name);
+            if network.edges[node as usize] != [UNDEFINED; 2] {
+                bail!("node {} is defined twice", name);
             }
-        })
-        .into_inner();
-    println!("nodes: {}, path: {}", l, path.join(" -> "));
+            network.edges[node as usize] = [network.intern(left), network.intern(right)];
+        }
+        if let Some(i) = network.edges.iter().position(|e| *e == [UNDEFINED; 2]) {
+            bail!("node {} is never defined", network.names[i]);
+        }
+        Ok(network)
+    }
 
-    l
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        self.edges.push([UNDEFINED; 2]);
+        id
+    }
+
+    fn id(&self, name: &str) -> Result<u32> {
+        self.ids
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("unknown node {}", name))
+    }
+
+    fn name(&self, id: u32) -> &str {
+        &self.names[id as usize]
+    }
+
+    /// Every node id whose name matches `predicate`.
+    fn nodes_where(&self, predicate: impl Fn(&str) -> bool) -> Vec<u32> {
+        (0..self.names.len() as u32)
+            .filter(|id| predicate(self.name(*id)))
+            .collect_vec()
+    }
+
+    /// Whether each node, by id, has a name matching `predicate`.
+    fn flags(&self, predicate: impl Fn(&str) -> bool) -> Vec<bool> {
+        self.names.iter().map(|n| predicate(n)).collect_vec()
+    }
+
+    /// The node reached from `node` by following instruction `step`, counted
+    /// from the start of the instructions and wrapping around.
+    fn step(&self, node: u32, step: usize) -> u32 {
+        self.edges[node as usize][self.directions[step % self.directions.len()]]
+    }
+
+    /// Number of steps from `start` to the first node matching `predicate`.
+    /// `None` if there is none: after one visit of every (node, instruction)
+    /// pair the walk can only repeat itself.
+    fn path_len(&self, start: u32, mut predicate: impl FnMut(u32) -> bool) -> Option<u64> {
+        let limit = self.names.len() * self.directions.len();
+        let mut node = start;
+        for i in 0..limit {
+            node = self.step(node, i);
+            if predicate(node) {
+                return Some(i as u64 + 1);
+            }
+        }
+        None
+    }
 }
+
 fn part1() -> Result<u64> {
-    let (_, (directions, m)) = parse(include_str!("input.txt"))?;
-    Ok(path_len(&directions, &m, "AAA", |x| x == "ZZZ"))
+    let network = Network::new(include_str!("input.txt"))?;
+    let end = network.id("ZZZ")?;
+    network
+        .path_len(network.id("AAA")?, |n| n == end)
+        .ok_or_else(|| anyhow!("ZZZ is unreachable"))
 }
 
 fn part2() -> Result<u64> {
-    let (_, (directions, m)) = parse(include_str!("input.txt"))?;
-    Ok(m.keys()
-        .filter(|k| k.ends_with('A'))
-        .map(|k| path_len(&directions, &m, k, |x| x.ends_with('Z')))
-        .sorted()
-        .rev()
-        .dedup()
-        .fold(1, |acc, i| acc.lcm(&i)))
+    let network = Network::new(include_str!("input.txt"))?;
+    let ends = network.flags(|n| n.ends_with('Z'));
+    let ghosts = network
+        .nodes_where(|n| n.ends_with('A'))
+        .into_iter()
+        .map(|start| Ghost::new(&network, start, &ends))
+        .collect_vec();
+    first_meeting(&ghosts).ok_or_else(|| anyhow!("the ghosts are never all on Z nodes at once"))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::rng::Rng;
+    use itertools::FoldWhile::{Continue, Done};
+
     #[test]
     fn day8_part1() -> Result<()> {
         let s = part1()?;
@@ -92,4 +168,124 @@ mod test {
         assert_eq!(s, 19185263738117);
         Ok(())
     }
+
+    #[test]
+    fn day8_network() -> Result<()> {
+        let network = Network::new("LLR\n\nAAA = (BBB, BBB)\nBBB = (AAA, ZZZ)\nZZZ = (ZZZ, ZZZ)")?;
+        assert_eq!(network.names, vec!["AAA", "BBB", "ZZZ"]);
+        assert_eq!(network.edges, vec![[1, 1], [0, 2], [2, 2]]);
+        let (start, end) = (network.id("AAA")?, network.id("ZZZ")?);
+        assert_eq!(network.path_len(start, |n| n == end), Some(6));
+        assert_eq!(network.path_len(end, |n| n == start), None);
+        assert!(network.id("CCC").is_err());
+
+        let ghosts = Network::new(
+            "LR
+
+11A = (11B, XXX)
+11B = (XXX, 11Z)
+11Z = (11B, XXX)
+22A = (22B, XXX)
+22B = (22C, 22C)
+22C = (22Z, 22Z)
+22Z = (22B, 22B)
+XXX = (XXX, XXX)",
+        )?;
+        let lengths = ghosts
+            .nodes_where(|n| n.ends_with('A'))
+            .into_iter()
+            .map(|s| ghosts.path_len(s, |n| ghosts.name(n).ends_with('Z')))
+            .collect_vec();
+        assert_eq!(lengths, vec![Some(2), Some(3)]);
+
+        assert!(Network::new("LR\n\nAAA = (BBB, AAA)").is_err());
+        assert!(Network::new("LR\n\nAAA = (AAA, AAA)\nAAA = (AAA, AAA)").is_err());
+        assert!(Network::new("LX\n\nAAA = (AAA, AAA)").is_err());
+        Ok(())
+    }
+
+    /// The walk as it used to be, over nested string maps, cloning a map and
+    /// a name on every step.
+    fn string_path_len(
+        directions: &[char],
+        map: &HashMap<String, HashMap<char, String>>,
+        start: &str,
+        mut predicate: impl FnMut(&str) -> bool,
+    ) -> u64 {
+        let mut loc = start.to_string();
+        directions
+            .iter()
+            .cycle()
+            .fold_while(1_u64, |acc, dir| {
+                let node = map.get(&loc).cloned().unwrap();
+                loc = node.get(dir).cloned().unwrap();
+                if predicate(&loc) {
+                    Done(acc)
+                } else {
+                    Continue(acc + 1)
+                }
+            })
+            .into_inner()
+    }
+
+    /// Compares walking a random network through string maps and through
+    /// interned ids. Run with
+    /// `cargo test --release day8_walk_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn day8_walk_benchmark() -> Result<()> {
+        let mut rng = Rng::new(49);
+        let name = |i: usize| {
+            (0..3)
+                .map(|p| (b'A' + (i / 26_usize.pow(p) % 26) as u8) as char)
+                .collect::<String>()
+        };
+        let nodes = 10_000;
+        let directions = (0..300)
+            .map(|_| if rng.below(2) == 0 { 'L' } else { 'R' })
+            .collect::<String>();
+        let input = (0..nodes)
+            .map(|i| {
+                format!(
+                    "{} = ({}, {})",
+                    name(i),
+                    name(rng.below(nodes)),
+                    name(rng.below(nodes))
+                )
+            })
+            .join("\n");
+        let input = format!("{}\n\n{}", directions, input);
+
+        let steps = 2_000_000;
+        let mut map = HashMap::new();
+        for l in input.lines().skip(2) {
+            let (_, (node, (left, right))) = parse_node(l).map_err(|e| e.to_owned())?;
+            let edges = [('L', left.to_string()), ('R', right.to_string())];
+            map.insert(node.to_string(), edges.into_iter().collect());
+        }
+        let chars = directions.chars().collect_vec();
+        let mut count = 0;
+        let start = std::time::Instant::now();
+        let walked = string_path_len(&chars, &map, "AAA", |_| {
+            count += 1;
+            count == steps
+        });
+        let string_time = start.elapsed();
+
+        let network = Network::new(&input)?;
+        let mut count = 0;
+        let start = std::time::Instant::now();
+        let interned = network.path_len(network.id("AAA")?, |_| {
+            count += 1;
+            count == steps
+        });
+        let interned_time = start.elapsed();
+
+        assert_eq!(Some(walked), interned);
+        println!(
+            "{} steps: string maps {:?}, interned ids {:?}",
+            steps, string_time, interned_time
+        );
+        Ok(())
+    }
 }
@@ -1,47 +1,82 @@
-use itertools::Itertools;
+use crate::stream::{self, Progress};
+use anyhow::{anyhow, Result};
+use std::io::BufRead;
 
-fn parse(input: &str) -> Vec<Vec<i32>> {
-    input
-        .lines()
-        .map(|l| l.split_whitespace().flat_map(|n| n.parse()).collect_vec())
-        .collect_vec()
+fn parse_line(line: &str) -> Result<Vec<i64>> {
+    line.split_whitespace()
+        .map(|n| n.parse().map_err(|e| anyhow!("bad value {:?}: {}", n, e)))
+        .collect()
+}
+
+/// Next and previous values of `seq`, `None` if they or any difference on
+/// the way overflow. Each row of differences overwrites the previous one in
+/// `buf`, so the work needs no recursion and a single allocation.
+fn extrapolate(seq: &[i64], buf: &mut Vec<i64>) -> Option<(i64, i64)> {
+    buf.clear();
+    buf.extend_from_slice(seq);
+    let (mut next, mut prev) = (0_i64, 0_i64);
+    let mut negate = false;
+    while buf.iter().any(|n| *n != 0) {
+        next = next.checked_add(*buf.last()?)?;
+        prev = if negate {
+            prev.checked_sub(buf[0])?
+        } else {
+            prev.checked_add(buf[0])?
+        };
+        negate = !negate;
+        for i in 1..buf.len() {
+            buf[i - 1] = buf[i].checked_sub(buf[i - 1])?;
+        }
+        buf.pop();
+    }
+    Some((next, prev))
+}
+
+/// Adds the next and previous values of line `n` (from 0) to `sums`.
+fn add_line(sums: (i64, i64), n: usize, line: &str, buf: &mut Vec<i64>) -> Result<(i64, i64)> {
+    let seq = parse_line(line)?;
+    extrapolate(&seq, buf)
+        .and_then(|(next, prev)| Some((sums.0.checked_add(next)?, sums.1.checked_add(prev)?)))
+        .ok_or_else(|| anyhow!("line {} overflows: {:?}", n + 1, line))
 }
 
-fn find_next(seq: Vec<i32>) -> i32 {
-    seq.last()
-        .map(|last| last + find_next(seq.iter().tuple_windows().map(|(x, y)| y - x).collect_vec()))
-        .unwrap_or_default()
+/// Sums of the next and previous values of every line of `input`.
+fn extrapolate_all(input: &str) -> Result<(i64, i64)> {
+    let mut buf = vec![];
+    input
+        .lines()
+        .enumerate()
+        .try_fold((0, 0), |sums, (n, line)| add_line(sums, n, line, &mut buf))
 }
 
-fn find_prev(seq: Vec<i32>) -> i32 {
-    seq.first()
-        .map(|first| {
-            first
-                - crate::day9::find_prev(
-                    seq.iter().tuple_windows().map(|(x, y)| y - x).collect_vec(),
-                )
-        })
-        .unwrap_or_default()
+fn part1() -> Result<i64> {
+    Ok(extrapolate_all(include_str!("input.txt"))?.0)
 }
 
-fn part1() -> i32 {
-    let seqs = parse(include_str!("input.txt"));
-    seqs.into_iter().map(find_next).sum()
+fn part2() -> Result<i64> {
+    Ok(extrapolate_all(include_str!("input.txt"))?.1)
 }
 
-fn part2() -> i32 {
-    let seqs = parse(include_str!("input.txt"));
-    seqs.into_iter().map(find_prev).sum()
+/// Sums of the next and previous values of every sequence read line by line
+/// from `reader`, computed in `i64` since stress inputs overflow `i32`.
+fn extrapolate_stream(reader: impl BufRead, report: impl FnMut(Progress)) -> Result<(i64, i64)> {
+    let mut sums = (0, 0);
+    let mut buf = vec![];
+    stream::for_each_line(reader, report, |n, line| {
+        sums = add_line(sums, n, line, &mut buf)?;
+        Ok(())
+    })?;
+    Ok(sums)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use anyhow::Result;
+    use itertools::Itertools;
 
     #[test]
     fn day9_part1() -> Result<()> {
-        let s = part1();
+        let s = part1()?;
         println!("{}", s);
         //assert_eq!(s, 114);
         assert!(s > 1417980928);
@@ -50,9 +85,37 @@ mod test {
 
     #[test]
     fn day9_part2() -> Result<()> {
-        let s = part2();
+        let s = part2()?;
         println!("{}", s);
         assert!(s < 20658);
         Ok(())
     }
+
+    #[test]
+    fn day9_stream() -> Result<()> {
+        let input = "0 3 6 9 12 15\n1 3 6 10 15 21\n10 13 16 21 30 45\n";
+        assert_eq!(extrapolate_stream(input.as_bytes(), |_| {})?, (114, 2));
+
+        let large = "3000000000 6000000000 9000000000\n";
+        assert_eq!(
+            extrapolate_stream(large.as_bytes(), |_| {})?,
+            (12_000_000_000, 0)
+        );
+        assert!(extrapolate_stream("1 2 x\n".as_bytes(), |_| {}).is_err());
+
+        let mut buf = vec![];
+        assert_eq!(
+            extrapolate(&[10, 13, 16, 21, 30, 45], &mut buf),
+            Some((68, 5))
+        );
+        assert_eq!(extrapolate(&[], &mut buf), Some((0, 0)));
+        assert_eq!(extrapolate(&[i64::MIN, i64::MAX], &mut buf), None);
+        let long = (0..5_000).map(|i| i * i).join(" ");
+        assert_eq!(extrapolate_all(&long)?, (5_000 * 5_000, 1));
+        let err = extrapolate_all("1 2\n0 9223372036854775807").unwrap_err();
+        assert!(err.to_string().starts_with("line 2 overflows"), "{}", err);
+        let err = extrapolate_stream("1 2\n9223372036854775807 0\n".as_bytes(), |_| {});
+        assert!(err.unwrap_err().to_string().starts_with("line 2 "));
+        Ok(())
+    }
 }
@@ -0,0 +1,309 @@
+use anyhow::{bail, Result};
+use std::ops::Range;
+
+/// One piece of an [`IntervalMap`]: `src..src + length` maps onto
+/// `dst..dst + length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Segment {
+    pub src: u64,
+    pub dst: u64,
+    pub length: u64,
+}
+
+impl Segment {
+    /// Fails when either end doesn't fit in a `u64`.
+    pub fn new(src: u64, dst: u64, length: u64) -> Result<Self> {
+        if src.checked_add(length).is_none() || dst.checked_add(length).is_none() {
+            bail!("segment {}..+{} -> {} overflows u64", src, length, dst);
+        }
+        Ok(Self { src, dst, length })
+    }
+
+    pub fn src_range(&self) -> Range<u64> {
+        self.src..self.src + self.length
+    }
+
+    pub fn dst_range(&self) -> Range<u64> {
+        self.dst..self.dst + self.length
+    }
+
+    pub fn contains(&self, src: u64) -> bool {
+        self.src <= src && src - self.src < self.length
+    }
+
+    pub fn map(&self, src: u64) -> u64 {
+        self.dst + (src - self.src)
+    }
+
+    fn is_identity(&self) -> bool {
+        self.src == self.dst
+    }
+}
+
+/// A map over `u64` made of non overlapping segments, each shifting its
+/// values by a constant offset. Values outside every segment map to
+/// themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntervalMap {
+    // sorted in src ascending order, no empty segments
+    segments: Vec<Segment>,
+}
+
+impl IntervalMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a map from `segments` in any order, failing on overlaps.
+    pub fn from_segments(segments: impl IntoIterator<Item = Segment>) -> Result<Self> {
+        let mut map = Self::new();
+        for s in segments {
+            map.insert(s)?;
+        }
+        Ok(map)
+    }
+
+    /// Adds a segment, failing if its source overlaps one already in the map.
+    /// Empty segments change nothing and are dropped.
+    pub fn insert(&mut self, segment: Segment) -> Result<()> {
+        if segment.length == 0 {
+            return Ok(());
+        }
+        let idx = self.segments.partition_point(|s| s.src < segment.src);
+        let overlaps =
+            |s: &Segment| s.src < segment.src + segment.length && segment.src < s.src + s.length;
+        for neighbour in [idx.checked_sub(1), Some(idx)].into_iter().flatten() {
+            if let Some(s) = self.segments.get(neighbour).filter(|s| overlaps(s)) {
+                bail!(
+                    "source {:?} overlaps existing source {:?}",
+                    segment.src_range(),
+                    s.src_range()
+                );
+            }
+        }
+        self.segments.insert(idx, segment);
+        Ok(())
+    }
+
+    /// The segment containing `src`, found by binary search.
+    pub fn segment_at(&self, src: u64) -> Option<&Segment> {
+        let idx = self.segments.partition_point(|s| s.src <= src);
+        idx.checked_sub(1)
+            .map(|i| &self.segments[i])
+            .filter(|s| s.contains(src))
+    }
+
+    pub fn get(&self, src: u64) -> u64 {
+        match self.segment_at(src) {
+            Some(s) => s.map(src),
+            None => src,
+        }
+    }
+
+    /// Splits `range` at the segment boundaries and maps each piece, returning
+    /// `(source, destination)` pairs of equal length in source order.
+    pub fn map_range(&self, range: Range<u64>) -> Vec<(Range<u64>, Range<u64>)> {
+        let mut pieces = Vec::new();
+        let mut start = range.start;
+        let mut idx = self.segments.partition_point(|s| s.src + s.length <= start);
+        while start < range.end {
+            let (end, dst) = match self.segments.get(idx) {
+                Some(s) if s.src <= start => {
+                    idx += 1;
+                    (range.end.min(s.src + s.length), s.map(start))
+                }
+                Some(s) => (range.end.min(s.src), start),
+                None => (range.end, start),
+            };
+            pieces.push((start..end, dst..dst + (end - start)));
+            start = end;
+        }
+        pieces
+    }
+
+    /// The explicit segments, in source order.
+    pub fn segments(&self) -> impl Iterator<Item = &Segment> + '_ {
+        self.segments.iter()
+    }
+
+    /// The unmapped ranges between the explicit segments, from 0 up to the
+    /// end of the last segment. Values in them map to themselves.
+    pub fn gaps(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        let starts = std::iter::once(0).chain(self.segments.iter().map(|s| s.src + s.length));
+        starts
+            .zip(self.segments.iter().map(|s| s.src))
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| start..end)
+    }
+
+    /// Every piece of the map in source order, gaps included as identity
+    /// segments, up to the end of the last explicit segment.
+    pub fn pieces(&self) -> impl Iterator<Item = Segment> + '_ {
+        let mut gaps = self.gaps().peekable();
+        let mut segments = self.segments.iter().peekable();
+        std::iter::from_fn(move || match (gaps.peek(), segments.peek()) {
+            (Some(g), Some(s)) if g.start < s.src => gaps.next().map(identity),
+            (_, Some(_)) => segments.next().cloned(),
+            (Some(_), None) => gaps.next().map(identity),
+            (None, None) => None,
+        })
+    }
+
+    /// Like `pieces` but carrying on with an identity piece to the end of the
+    /// `u64` range.
+    fn covering_pieces(&self) -> impl Iterator<Item = Segment> + '_ {
+        let end = self.segments.last().map_or(0, |s| s.src + s.length);
+        let tail = Some(identity(end..u64::MAX)).filter(|t| t.length > 0);
+        self.pieces().chain(tail)
+    }
+
+    /// Every source value mapping into `range`, as ranges in source order.
+    /// Several ranges can come back when segments share destinations.
+    pub fn preimage(&self, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut sources: Vec<Range<u64>> = Vec::new();
+        for p in self.covering_pieces() {
+            let start = range.start.max(p.dst);
+            let end = range.end.min(p.dst + p.length);
+            if start >= end {
+                continue;
+            }
+            let src = p.src + (start - p.dst)..p.src + (end - p.dst);
+            match sources.last_mut() {
+                Some(last) if last.end == src.start => last.end = src.end,
+                _ => sources.push(src),
+            }
+        }
+        sources
+    }
+
+    /// The map applying `self` then `next`, as a single interval map.
+    pub fn compose(&self, next: &IntervalMap) -> Self {
+        let segments = self
+            .covering_pieces()
+            .flat_map(|p| {
+                next.map_range(p.dst_range())
+                    .into_iter()
+                    .map(move |(src, dst)| Segment {
+                        src: p.src + (src.start - p.dst),
+                        dst: dst.start,
+                        length: src.end - src.start,
+                    })
+            })
+            .collect::<Vec<_>>();
+        Self {
+            segments: normalize(segments),
+        }
+    }
+
+    /// The same map with identity segments dropped and touching segments
+    /// sharing an offset merged, so two maps are equal as functions exactly
+    /// when their normalized forms are equal.
+    pub fn normalized(&self) -> Self {
+        Self {
+            segments: normalize(self.segments.clone()),
+        }
+    }
+
+    /// The map going from destinations back to sources, failing if two
+    /// segments share destinations. Gaps stay identity, so this is the true
+    /// inverse only when the segments' sources and destinations cover the
+    /// same values.
+    pub fn invert(&self) -> Result<Self> {
+        Self::from_segments(self.segments.iter().map(|s| Segment {
+            src: s.dst,
+            dst: s.src,
+            length: s.length,
+        }))
+    }
+}
+
+/// Drops identity segments and merges touching ones, `segments` being sorted
+/// by source.
+fn normalize(segments: Vec<Segment>) -> Vec<Segment> {
+    let mut merged: Vec<Segment> = Vec::new();
+    for s in segments.into_iter().filter(|s| !s.is_identity()) {
+        match merged.last_mut() {
+            Some(last) if last.src + last.length == s.src && last.dst + last.length == s.dst => {
+                last.length += s.length;
+            }
+            _ => merged.push(s),
+        }
+    }
+    merged
+}
+
+fn identity(range: Range<u64>) -> Segment {
+    Segment {
+        src: range.start,
+        dst: range.start,
+        length: range.end - range.start,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interval_map() -> Result<()> {
+        let map = IntervalMap::from_segments([
+            Segment::new(50, 52, 48)?,
+            Segment::new(98, 50, 2)?,
+            Segment::new(10, 10, 0)?,
+        ])?;
+        assert_eq!(map.get(0), 0);
+        assert_eq!(map.get(49), 49);
+        assert_eq!(map.get(50), 52);
+        assert_eq!(map.get(97), 99);
+        assert_eq!(map.get(99), 51);
+        assert_eq!(map.get(100), 100);
+        assert_eq!(map.segments().count(), 2);
+        assert_eq!(map.gaps().collect::<Vec<_>>(), vec![0..50]);
+        let pieces = map.pieces().map(|p| (p.src, p.dst)).collect::<Vec<_>>();
+        assert_eq!(pieces, vec![(0, 0), (50, 52), (98, 50)]);
+
+        let inverse = map.invert()?;
+        assert!((0..120).all(|v| inverse.get(map.get(v)) == v));
+
+        let mut overlapping = map.clone();
+        assert!(overlapping.insert(Segment::new(40, 0, 11)?).is_err());
+        assert!(overlapping.insert(Segment::new(99, 0, 5)?).is_err());
+        assert!(overlapping.insert(Segment::new(100, 0, 5)?).is_ok());
+        assert!(Segment::new(u64::MAX, 0, 1).is_err());
+
+        let pieces = map.map_range(40..99);
+        assert_eq!(
+            pieces,
+            vec![(40..50, 40..50), (50..98, 52..100), (98..99, 50..51)]
+        );
+        assert_eq!(map.map_range(60..70), vec![(60..70, 62..72)]);
+        assert_eq!(
+            map.map_range(99..105),
+            vec![(99..100, 51..52), (100..105, 100..105)]
+        );
+        assert!(map.map_range(5..5).is_empty());
+
+        let next =
+            IntervalMap::from_segments([Segment::new(0, 200, 60)?, Segment::new(150, 0, 10)?])?;
+        let composed = map.compose(&next);
+        assert!((0..300).all(|v| composed.get(v) == next.get(map.get(v))));
+        assert_eq!(composed.segment_at(150), Some(&Segment::new(150, 0, 10)?));
+
+        let split = IntervalMap::from_segments([Segment::new(0, 10, 5)?, Segment::new(5, 15, 5)?])?;
+        let joined =
+            IntervalMap::from_segments([Segment::new(0, 10, 10)?, Segment::new(20, 20, 3)?])?;
+        assert_ne!(split, joined);
+        assert_eq!(split.normalized(), joined.normalized());
+
+        assert_eq!(map.preimage(50..53), vec![50..51, 98..100]);
+        assert_eq!(map.preimage(99..102), vec![97..98, 100..102]);
+        let folded = IntervalMap::from_segments([Segment::new(0, 10, 5)?])?;
+        assert_eq!(folded.preimage(10..11), vec![0..1, 10..11]);
+
+        let shifted = IntervalMap::from_segments([Segment::new(0, 5, 5)?])?;
+        assert_eq!(shifted.invert()?.get(7), 2);
+        let clash = IntervalMap::from_segments([Segment::new(0, 5, 5)?, Segment::new(10, 7, 5)?])?;
+        assert!(clash.invert().is_err());
+        Ok(())
+    }
+}
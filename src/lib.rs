@@ -7,3 +7,6 @@ mod day6;
 mod day7;
 mod day8;
 mod day9;
+mod interval_map;
+mod rng;
+mod stream;
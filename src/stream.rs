@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::io::BufRead;
+
+/// How often, in lines, streaming solvers report their progress.
+pub const PROGRESS_EVERY: usize = 1_000_000;
+
+/// Lines and bytes consumed so far by a streaming solver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+/// Feeds `reader` to `f` one line at a time, with the line number and without
+/// its line ending. A single buffer is reused for every line so memory stays
+/// constant whatever the input size. `report` is called every
+/// [`PROGRESS_EVERY`] lines and at the end, unless that was just reported.
+pub fn for_each_line<R: BufRead>(
+    mut reader: R,
+    mut report: impl FnMut(Progress),
+    mut f: impl FnMut(usize, &str) -> Result<()>,
+) -> Result<Progress> {
+    let mut progress = Progress::default();
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        let read = reader.read_line(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        let line = buf.strip_suffix('\n').unwrap_or(&buf);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        f(progress.lines, line)?;
+
+        progress.lines += 1;
+        progress.bytes += read;
+        if progress.lines % PROGRESS_EVERY == 0 {
+            report(progress);
+        }
+    }
+    if progress.lines == 0 || progress.lines % PROGRESS_EVERY != 0 {
+        report(progress);
+    }
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stream_lines() -> Result<()> {
+        let mut lines = Vec::new();
+        let mut reports = Vec::new();
+        let progress = for_each_line(
+            "a\r\nbc\n\nd".as_bytes(),
+            |p| reports.push(p),
+            |n, l| {
+                lines.push((n, l.to_string()));
+                Ok(())
+            },
+        )?;
+        assert_eq!(
+            lines,
+            vec![
+                (0, "a".to_string()),
+                (1, "bc".to_string()),
+                (2, "".to_string()),
+                (3, "d".to_string())
+            ]
+        );
+        assert_eq!(progress, Progress { lines: 4, bytes: 8 });
+        assert_eq!(reports, vec![progress]);
+
+        let mut reports = Vec::new();
+        let input = "\n".repeat(PROGRESS_EVERY);
+        for_each_line(input.as_bytes(), |p| reports.push(p), |_, _| Ok(()))?;
+        assert_eq!(
+            reports,
+            vec![Progress {
+                lines: PROGRESS_EVERY,
+                bytes: PROGRESS_EVERY
+            }]
+        );
+        Ok(())
+    }
+}